@@ -1,7 +1,7 @@
-use std::env;
+use std::{collections::HashMap, env, sync::Arc};
 
-use anyhow::Result;
 use log::{debug, warn};
+use reqwest::Method;
 // use reqwest::StatusCode;
 use crate::config;
 use s3::{
@@ -9,33 +9,177 @@ use s3::{
     error::S3Error,
     region::Region,
     request::ResponseData,
-    serde_types::Object,
+    serde_types::{HeadObjectResult, Object},
     Bucket,
 };
 use serde::Deserialize;
+use thiserror::Error;
+
+mod admin;
+mod credentials;
+mod document;
+mod list;
+mod options;
+mod presign;
+mod signed;
+mod stream;
+mod sync;
+#[cfg(test)]
+mod testing;
+
+pub use admin::ExpirationRule;
+pub use credentials::{
+    CredentialsProvider, CredentialsRefresh, CredentialsSource, EnvProvider, FileProvider,
+    StaticProvider, WebIdentityProvider,
+};
+pub use document::Compression;
+pub use list::{ListEntry, ListOptions, ListStream};
+pub use options::PutOptions;
+use signed::SignedRequest;
+pub use stream::MultipartSession;
+pub use sync::{SyncAction, SyncEvent, SyncOptions, SyncProgress, SyncReport};
+
+///maximum number of keys of a DeleteObjects request
+const DELETE_BATCH_SIZE: usize = 1000;
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+const DEFAULT_MULTIPART_THRESHOLD: usize = 8 * 1024 * 1024;
+///minimum size of the parts of a multipart upload, except the last one
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+///maximum size of a single upload request or of a multipart upload part
+const MAX_PUT_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    S3(S3Error),
+    #[error("request failed with status {0}: {1}")]
+    Http(u16, String),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid header value {0}")]
+    InvalidHeader(String),
+    #[error("no credentials to sign the request")]
+    MissingCredentials,
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("invalid lifecycle rule {0}: {1}")]
+    InvalidLifecycleRule(String, &'static str),
+    #[error("invalid config: {0}")]
+    InvalidConfig(&'static str),
+    #[error("{0} compression requires the minio-{0} feature")]
+    CompressionDisabled(&'static str),
+}
+
+///the http errors are lifted to [`Error::Http`] so they can be matched
+///whatever the request that failed
+impl From<S3Error> for Error {
+    fn from(error: S3Error) -> Self {
+        match error {
+            S3Error::Http(status, body) => Error::Http(status, body),
+            error => Error::S3(error),
+        }
+    }
+}
+
+impl Error {
+    ///return true if the object or the bucket does not exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Http(404, _))
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+///Addressing of the buckets in the urls.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Addressing {
+    ///`<endpoint>/<bucket>/<key>`, used by minio
+    #[default]
+    Path,
+    ///`<bucket>.<endpoint>/<key>`, the default of aws s3
+    Virtual,
+}
 
 #[derive(Deserialize, Clone, Debug, Default)]
 pub struct Minio {
+    ///default bucket, used by `client`
+    #[serde(default)]
     pub name: String,
+    ///endpoint url, the aws endpoint of the region is used if empty
+    #[serde(default)]
     pub service: String,
+    ///us-east-1 if unset
+    pub region: Option<String>,
+    #[serde(default)]
+    pub addressing: Addressing,
+    ///other buckets of the endpoint by alias, used by `bucket`
+    #[serde(default)]
+    pub buckets: HashMap<String, String>,
     pub access_key: Option<String>,
     pub secret_key: Option<String>,
     pub security_token: Option<String>,
     pub session_token: Option<String>,
     pub expiration: Option<Rfc3339OffsetDateTime>,
+    ///source of the credentials refreshed by `refresh_credentials`
+    #[serde(default)]
+    pub credentials: CredentialsSource,
+    ///size in bytes above which the streamed uploads use multipart instead
+    ///of a single request, 8 MiB by default and at most 5 GiB
+    pub multipart_threshold: Option<usize>,
+    ///size in bytes of the parts of the multipart uploads, 8 MiB by default,
+    ///between 5 MiB and 5 GiB
+    pub part_size: Option<usize>,
     #[serde(skip_deserializing)]
     pub client: Option<Client>,
+    ///clients of the `buckets` by alias
+    #[serde(skip_deserializing)]
+    pub clients: HashMap<String, Client>,
+    #[serde(skip_deserializing)]
+    pub refresh: Option<Arc<CredentialsRefresh>>,
     #[serde(skip_deserializing)]
     pub prefix: Option<String>,
 }
 
 impl Minio {
-    ///update the client with the structure data
-    pub fn update(&mut self) -> Result<&mut Self, S3Error> {
-        self.client = Some(Client::new(self)?);
+    ///update the clients with the structure data
+    pub fn update(&mut self) -> Result<&mut Self> {
+        let client = Client::new(self)?;
+        self.clients = self
+            .buckets
+            .iter()
+            .map(|(alias, name)| (alias.to_owned(), client.with_bucket(name)))
+            .collect();
+        self.client = (!self.name.is_empty()).then_some(client);
         Ok(self)
     }
 
+    ///client of the bucket declared under the alias in `buckets`
+    pub fn bucket(&self, alias: &str) -> Option<&Client> {
+        self.clients.get(alias)
+    }
+
+    ///region of the endpoint, or the aws region if there is no endpoint
+    fn region(&self) -> Result<Region> {
+        let region = self.region.as_deref().unwrap_or(DEFAULT_REGION);
+        if self.service.is_empty() {
+            return Ok(region.parse().map_err(S3Error::from)?);
+        }
+        Ok(Region::Custom {
+            region: region.to_owned(),
+            endpoint: self.service.to_owned(),
+        })
+    }
+
     ///fetch the secret from the environment
     pub fn set_secrets(&mut self) -> &mut Self {
         let prefix = if let Some(ref pref) = self.prefix {
@@ -72,47 +216,155 @@ impl Minio {
     info!("code {}: {}", code, reason);
     Ok(status)
 } */
+///Metadata of an object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectMetadata {
+    pub size: u64,
+    ///etag without the quotes
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub cache_control: Option<String>,
+    pub last_modified: Option<String>,
+    ///user metadata, from the `x-amz-meta-*` headers without the prefix
+    pub metadata: HashMap<String, String>,
+}
+
+impl From<HeadObjectResult> for ObjectMetadata {
+    fn from(head: HeadObjectResult) -> Self {
+        ObjectMetadata {
+            size: head.content_length.unwrap_or_default().max(0) as u64,
+            etag: head.e_tag.map(|e| e.trim_matches('"').to_owned()),
+            content_type: head.content_type,
+            content_disposition: head.content_disposition,
+            cache_control: head.cache_control,
+            last_modified: head.last_modified,
+            metadata: head.metadata.unwrap_or_default(),
+        }
+    }
+}
+
+///Object that could not be deleted by [`Client::delete_objects`].
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeleteError {
+    pub key: String,
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct DeleteResult {
+    #[serde(default, rename = "Error")]
+    errors: Vec<DeleteError>,
+}
+
+///build the body of a quiet DeleteObjects request
+fn delete_body<S: AsRef<str>>(paths: &[S]) -> String {
+    let mut body = String::from("<Delete><Quiet>true</Quiet>");
+    for path in paths {
+        let key = path.as_ref().trim_start_matches('/');
+        body += &format!(
+            "<Object><Key>{}</Key></Object>",
+            quick_xml::escape::escape(key)
+        );
+    }
+    body + "</Delete>"
+}
+
 #[derive(Clone, Debug)]
-pub struct Client(Bucket);
+pub struct Client {
+    bucket: Bucket,
+    ///http client of the signed requests, shared by the clones
+    http: reqwest::Client,
+    ///size above which the streamed uploads use multipart
+    multipart_threshold: usize,
+    ///size of the multipart upload parts
+    part_size: usize,
+}
 
 impl Client {
     ///create a new minio client with the given config
-    pub fn new(config: &config::Minio) -> Result<Self, S3Error> {
+    pub fn new(config: &config::Minio) -> Result<Self> {
         let credentials = Credentials {
             access_key: config.access_key.to_owned(),
             secret_key: config.secret_key.to_owned(),
             security_token: config.security_token.to_owned(),
             session_token: config.session_token.to_owned(),
-            expiration: config.expiration,
-        };
-        let region = Region::Custom {
-            region: "us-east-1".into(),
-            endpoint: config.service.to_owned(),
+            //rust-s3 replace expired credentials with the default ones, the
+            //expiration is only used by `refresh_credentials`
+            expiration: None,
         };
-        let bucket = Bucket::new(&config.name, region, credentials)?;
-        Ok(Client(bucket.with_path_style()))
+        let mut bucket = Bucket::new(&config.name, config.region()?, credentials)?;
+        match config.addressing {
+            Addressing::Path => bucket.set_path_style(),
+            Addressing::Virtual => bucket.set_subdomain_style(),
+        }
+        let part_size = config.part_size.unwrap_or(DEFAULT_PART_SIZE);
+        if !(MIN_PART_SIZE..=MAX_PUT_SIZE).contains(&part_size) {
+            return Err(Error::InvalidConfig(
+                "the part size must be between 5 MiB and 5 GiB",
+            ));
+        }
+        let multipart_threshold = config
+            .multipart_threshold
+            .unwrap_or(DEFAULT_MULTIPART_THRESHOLD);
+        if multipart_threshold > MAX_PUT_SIZE {
+            return Err(Error::InvalidConfig(
+                "the multipart threshold must be at most 5 GiB",
+            ));
+        }
+        let mut http = reqwest::Client::builder();
+        if let Some(timeout) = bucket.request_timeout() {
+            http = http.timeout(timeout);
+        }
+        Ok(Client {
+            bucket,
+            http: http.build()?,
+            multipart_threshold,
+            part_size,
+        })
     }
 
-    pub async fn put_object<S>(&self, data: &[u8], path: S) -> Result<ResponseData, S3Error>
+    ///client of another bucket of the same endpoint, sharing the
+    ///credentials and the settings
+    pub fn with_bucket(&self, name: &str) -> Self {
+        let mut bucket = self.bucket.clone();
+        bucket.name = name.to_owned();
+        Client {
+            bucket,
+            http: self.http.clone(),
+            multipart_threshold: self.multipart_threshold,
+            part_size: self.part_size,
+        }
+    }
+
+    ///name of the bucket
+    pub fn name(&self) -> &str {
+        &self.bucket.name
+    }
+
+    pub async fn put_object<S>(&self, data: &[u8], path: S) -> Result<ResponseData>
     where
         S: AsRef<str>,
     {
-        self.0.put_object(path, data).await
+        Ok(self.bucket.put_object(path, data).await?)
     }
 
-    pub async fn get_object<S>(&self, path: S) -> Result<ResponseData, S3Error>
+    pub async fn get_object<S>(&self, path: S) -> Result<ResponseData>
     where
         S: AsRef<str>,
     {
-        self.0.get_object(path).await
+        Ok(self.bucket.get_object(path).await?)
     }
 
     pub async fn list_object(
         &self,
         path: String,
         delimiter: Option<String>,
-    ) -> Result<Vec<Object>, anyhow::Error> {
-        let raw_list = self.0.list(path, delimiter).await?;
+    ) -> Result<Vec<Object>> {
+        let raw_list = self.bucket.list(path, delimiter).await?;
         debug!("raw bucket object: {:#?}", raw_list);
         let list: Vec<Object> = raw_list
             .iter()
@@ -120,7 +372,75 @@ impl Client {
             .collect();
         Ok(list)
     }
+
+    ///delete the object, succeed if it does not exist
+    pub async fn delete_object<S>(&self, path: S) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        self.bucket.delete_object(path).await?;
+        Ok(())
+    }
+
+    ///delete the objects with DeleteObjects requests of up to 1000 keys,
+    ///return the objects that could not be deleted.
+    pub async fn delete_objects<S>(&self, paths: &[S]) -> Result<Vec<DeleteError>>
+    where
+        S: AsRef<str>,
+    {
+        let mut errors = Vec::new();
+        for batch in paths.chunks(DELETE_BATCH_SIZE) {
+            let response = SignedRequest::new(&self.bucket, Method::POST, "")
+                .query("delete", "")
+                .body_with_md5(delete_body(batch).into_bytes())?
+                .send(&self.http)
+                .await?;
+            let body = String::from_utf8_lossy(&response.body);
+            if body.trim().is_empty() {
+                continue;
+            }
+            let result: DeleteResult = quick_xml::de::from_str(&body)
+                .map_err(|e| Error::InvalidResponse(e.to_string()))?;
+            errors.extend(result.errors);
+        }
+        if !errors.is_empty() {
+            warn!("failed to delete {} objects", errors.len());
+        }
+        Ok(errors)
+    }
+
+    ///fetch the size, etag, content type and metadata of the object
+    pub async fn head_object<S>(&self, path: S) -> Result<ObjectMetadata>
+    where
+        S: AsRef<str>,
+    {
+        let (head, _) = self.bucket.head_object(path).await?;
+        Ok(head.into())
+    }
+
+    ///return true if the object exists
+    pub async fn exists<S>(&self, path: S) -> Result<bool>
+    where
+        S: AsRef<str>,
+    {
+        match self.head_object(path).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    ///copy an object of the bucket server side
+    pub async fn copy_object<F, T>(&self, from: F, to: T) -> Result<()>
+    where
+        F: AsRef<str>,
+        T: AsRef<str>,
+    {
+        self.bucket.copy_object_internal(from, to).await?;
+        Ok(())
+    }
 }
+
 /* #[cfg(test)]
 mod tests {
     use std::str;
@@ -150,3 +470,169 @@ mod tests {
         assert!(res.is_ok());
     }
 } */
+
+#[cfg(test)]
+mod test_minio {
+    use httpmock::prelude::*;
+
+    use super::{testing::client, *};
+
+    #[test]
+    fn test_addressing() {
+        use figment::{
+            providers::{Format, Yaml},
+            Figment,
+        };
+
+        let yaml = "
+service: https://s3.example.com
+name: assets
+region: eu-west-3
+addressing: virtual
+buckets:
+  logs: assets-logs
+";
+        let mut config: Minio = Figment::new().merge(Yaml::string(yaml)).extract().unwrap();
+        config.update().unwrap();
+        let client = config.client.as_ref().unwrap();
+        assert_eq!(client.bucket.url(), "https://assets.s3.example.com");
+        assert_eq!(client.bucket.region().to_string(), "eu-west-3");
+        let logs = config.bucket("logs").unwrap();
+        assert_eq!(logs.name(), "assets-logs");
+        assert_eq!(logs.bucket.url(), "https://assets-logs.s3.example.com");
+        assert!(config.bucket("assets").is_none());
+    }
+
+    #[test]
+    fn test_upload_sizes() {
+        let config = |part_size, multipart_threshold| Minio {
+            name: "bucket".to_owned(),
+            part_size,
+            multipart_threshold,
+            ..Default::default()
+        };
+        let client = Client::new(&config(None, None)).unwrap();
+        assert_eq!(client.part_size, DEFAULT_PART_SIZE);
+        assert_eq!(client.multipart_threshold, DEFAULT_MULTIPART_THRESHOLD);
+        for (part_size, threshold) in [
+            (Some(MIN_PART_SIZE - 1), None),
+            (Some(MAX_PUT_SIZE + 1), None),
+            (None, Some(usize::MAX)),
+        ] {
+            let res = Client::new(&config(part_size, threshold));
+            assert!(matches!(res, Err(Error::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn test_aws_region() {
+        let config = Minio {
+            name: "assets".to_owned(),
+            region: Some("eu-west-1".to_owned()),
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        assert_eq!(
+            client.bucket.url(),
+            "https://s3-eu-west-1.amazonaws.com/assets"
+        );
+        let mut config = Minio {
+            name: String::new(),
+            buckets: HashMap::from([("logs".to_owned(), "logs".to_owned())]),
+            ..config
+        };
+        config.update().unwrap();
+        assert!(config.client.is_none());
+        assert!(config.bucket("logs").is_some());
+    }
+
+    #[test]
+    fn test_delete_body() {
+        let body = delete_body(&["a.txt", "/dir/b&c.txt"]);
+        let expected = "<Delete><Quiet>true</Quiet>\
+            <Object><Key>a.txt</Key></Object>\
+            <Object><Key>dir/b&amp;c.txt</Key></Object></Delete>";
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_head_object() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD).path("/bucket/file.txt");
+                then.status(200)
+                    .header("content-length", "12")
+                    .header("content-type", "text/plain")
+                    .header("cache-control", "no-cache")
+                    .header("etag", "\"abc\"")
+                    .header("x-amz-meta-owner", "alice");
+            })
+            .await;
+        let metadata = client(&server).head_object("file.txt").await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(metadata.size, 12);
+        assert_eq!(metadata.etag.as_deref(), Some("abc"));
+        assert_eq!(metadata.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(metadata.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(
+            metadata.metadata.get("owner").map(String::as_str),
+            Some("alice")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exists() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD)
+                    .path("/bucket/missing.txt");
+                then.status(404);
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD)
+                    .path("/bucket/denied.txt");
+                then.status(403);
+            })
+            .await;
+        let client = client(&server);
+        assert!(!client.exists("missing.txt").await.unwrap());
+        let res = client.exists("denied.txt").await;
+        assert!(matches!(res, Err(Error::Http(403, _))));
+        let res = client.get_object("missing.txt").await.unwrap_err();
+        assert!(res.is_not_found());
+    }
+
+    #[tokio::test]
+    async fn test_delete_objects() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/")
+                    .query_param_exists("delete")
+                    .header_exists("content-md5")
+                    .header_exists("authorization")
+                    .body_contains("<Key>b.txt</Key>");
+                then.status(200).body(
+                    "<DeleteResult><Error><Key>b.txt</Key><Code>AccessDenied</Code>\
+                    <Message>Access Denied</Message></Error></DeleteResult>",
+                );
+            })
+            .await;
+        let errors = client(&server)
+            .delete_objects(&["a.txt", "b.txt"])
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        let expected = DeleteError {
+            key: "b.txt".to_owned(),
+            code: "AccessDenied".to_owned(),
+            message: "Access Denied".to_owned(),
+        };
+        assert_eq!(errors, vec![expected]);
+    }
+}
@@ -0,0 +1,312 @@
+use quick_xml::escape::escape;
+use reqwest::Method;
+
+use super::{signed::SignedRequest, Client, Error, Result, DEFAULT_REGION};
+
+///Lifecycle rule expiring the objects of a prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpirationRule {
+    pub id: String,
+    ///prefix of the keys the rule applies to, every key if empty
+    pub prefix: String,
+    ///days after which the current versions expire
+    pub days: Option<u32>,
+    ///days after which the noncurrent versions are deleted, for versioned
+    ///buckets
+    pub noncurrent_days: Option<u32>,
+}
+
+impl ExpirationRule {
+    ///the rule must expire something, after at least one day
+    fn validate(&self) -> Result<()> {
+        let invalid = |reason| Err(Error::InvalidLifecycleRule(self.id.to_owned(), reason));
+        match (self.days, self.noncurrent_days) {
+            (None, None) => invalid("days or noncurrent_days must be set"),
+            (Some(0), _) | (_, Some(0)) => invalid("the days must be positive"),
+            _ => Ok(()),
+        }
+    }
+
+    fn xml(&self) -> String {
+        let mut rule = format!(
+            "<Rule><ID>{}</ID><Filter><Prefix>{}</Prefix></Filter><Status>Enabled</Status>",
+            escape(&self.id),
+            escape(&self.prefix)
+        );
+        if let Some(days) = self.days {
+            rule += &format!("<Expiration><Days>{days}</Days></Expiration>");
+        }
+        if let Some(days) = self.noncurrent_days {
+            rule += &format!(
+                "<NoncurrentVersionExpiration><NoncurrentDays>{days}</NoncurrentDays>\
+                </NoncurrentVersionExpiration>"
+            );
+        }
+        rule + "</Rule>"
+    }
+}
+
+fn lifecycle_body(rules: &[ExpirationRule]) -> String {
+    let rules: String = rules.iter().map(ExpirationRule::xml).collect();
+    format!("<LifecycleConfiguration>{rules}</LifecycleConfiguration>")
+}
+
+impl Client {
+    fn bucket_request(&self, method: Method) -> SignedRequest<'_> {
+        SignedRequest::new(&self.bucket, method, "")
+    }
+
+    ///return true if the bucket exists
+    pub async fn bucket_exists(&self) -> Result<bool> {
+        match self.bucket_request(Method::HEAD).send(&self.http).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    ///create the bucket in the region of the client, return false if it
+    ///already exists and is accessible by the credentials.
+    ///
+    ///The existence is checked first, as us-east-1 answers a success to the
+    ///creation of a bucket already owned.
+    pub async fn create_bucket(&self) -> Result<bool> {
+        if self.bucket_exists().await? {
+            return Ok(false);
+        }
+        let region = self.bucket.region().to_string();
+        let mut request = self.bucket_request(Method::PUT);
+        if region != DEFAULT_REGION {
+            request = request.body(
+                format!(
+                    "<CreateBucketConfiguration><LocationConstraint>{}</LocationConstraint>\
+                    </CreateBucketConfiguration>",
+                    escape(&region)
+                )
+                .into_bytes(),
+            );
+        }
+        match request.send(&self.http).await {
+            Ok(_) => Ok(true),
+            Err(Error::Http(409, body)) if body.contains("BucketAlreadyOwnedByYou") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    ///delete the bucket, it must be empty
+    pub async fn delete_bucket(&self) -> Result<()> {
+        self.bucket_request(Method::DELETE).send(&self.http).await?;
+        Ok(())
+    }
+
+    ///enable or suspend the versioning of the bucket
+    pub async fn set_versioning(&self, enabled: bool) -> Result<()> {
+        let status = if enabled { "Enabled" } else { "Suspended" };
+        let body =
+            format!("<VersioningConfiguration><Status>{status}</Status></VersioningConfiguration>");
+        self.bucket_request(Method::PUT)
+            .query("versioning", "")
+            .body(body.into_bytes())
+            .send(&self.http)
+            .await?;
+        Ok(())
+    }
+
+    ///replace the lifecycle rules of the bucket, remove them if empty. Fail
+    ///if a rule expires nothing.
+    pub async fn set_lifecycle(&self, rules: &[ExpirationRule]) -> Result<()> {
+        for rule in rules {
+            rule.validate()?;
+        }
+        if rules.is_empty() {
+            self.bucket_request(Method::DELETE)
+                .query("lifecycle", "")
+                .send(&self.http)
+                .await?;
+            return Ok(());
+        }
+        self.bucket_request(Method::PUT)
+            .query("lifecycle", "")
+            .body_with_md5(lifecycle_body(rules).into_bytes())?
+            .send(&self.http)
+            .await?;
+        Ok(())
+    }
+
+    ///replace the json policy of the bucket, remove it if `None`
+    pub async fn set_policy(&self, policy: Option<&str>) -> Result<()> {
+        let request = match policy {
+            Some(policy) => self
+                .bucket_request(Method::PUT)
+                .body(policy.as_bytes().to_vec()),
+            None => self.bucket_request(Method::DELETE),
+        };
+        request.query("policy", "").send(&self.http).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_admin {
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::{testing, Minio};
+
+    fn client(server: &MockServer, region: Option<&str>) -> Client {
+        let config = Minio {
+            region: region.map(str::to_owned),
+            ..testing::config(server)
+        };
+        Client::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_lifecycle_body() {
+        let rules = [ExpirationRule {
+            id: "tmp".to_owned(),
+            prefix: "tmp/".to_owned(),
+            days: Some(7),
+            noncurrent_days: Some(1),
+        }];
+        let expected = "<LifecycleConfiguration><Rule><ID>tmp</ID><Filter><Prefix>tmp/</Prefix>\
+            </Filter><Status>Enabled</Status><Expiration><Days>7</Days></Expiration>\
+            <NoncurrentVersionExpiration><NoncurrentDays>1</NoncurrentDays>\
+            </NoncurrentVersionExpiration></Rule></LifecycleConfiguration>";
+        assert_eq!(lifecycle_body(&rules), expected);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_lifecycle() {
+        let server = MockServer::start_async().await;
+        let client = client(&server, None);
+        let mut rule = ExpirationRule {
+            id: "tmp".to_owned(),
+            ..Default::default()
+        };
+        let res = client.set_lifecycle(&[rule.clone()]).await;
+        assert!(matches!(res, Err(Error::InvalidLifecycleRule(id, _)) if id == "tmp"));
+        rule.days = Some(0);
+        let res = client.set_lifecycle(&[rule.clone()]).await;
+        assert!(matches!(res, Err(Error::InvalidLifecycleRule(..))));
+        rule.noncurrent_days = Some(1);
+        rule.days = None;
+        assert!(rule.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bucket_exists() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD).path("/bucket/");
+                then.status(404);
+            })
+            .await;
+        let client = client(&server, None);
+        assert!(!client.bucket_exists().await.unwrap());
+        mock.delete_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD).path("/bucket/");
+                then.status(200);
+            })
+            .await;
+        assert!(client.bucket_exists().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_bucket() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/")
+                    .body_contains("<LocationConstraint>eu-west-3</LocationConstraint>");
+                then.status(409)
+                    .body("<Error><Code>BucketAlreadyOwnedByYou</Code><Message></Message></Error>");
+            })
+            .await;
+        let client = client(&server, Some("eu-west-3"));
+        assert!(!client.create_bucket().await.unwrap());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_create_existing_bucket() {
+        let server = MockServer::start_async().await;
+        let head = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::HEAD).path("/bucket/");
+                then.status(200);
+            })
+            .await;
+        let create = server
+            .mock_async(|when, then| {
+                when.method(PUT).path("/bucket/");
+                then.status(200);
+            })
+            .await;
+        assert!(!client(&server, None).create_bucket().await.unwrap());
+        head.assert_async().await;
+        create.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_bucket_conflict() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(PUT).path("/bucket/");
+                then.status(409)
+                    .body("<Error><Code>BucketAlreadyExists</Code></Error>");
+            })
+            .await;
+        let res = client(&server, None).create_bucket().await;
+        assert!(matches!(res, Err(Error::Http(409, _))));
+    }
+
+    #[tokio::test]
+    async fn test_bucket_settings() {
+        let server = MockServer::start_async().await;
+        let versioning = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/")
+                    .query_param_exists("versioning")
+                    .body_contains("<Status>Enabled</Status>");
+                then.status(200);
+            })
+            .await;
+        let lifecycle = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/")
+                    .query_param_exists("lifecycle")
+                    .header_exists("content-md5")
+                    .body_contains("<Days>30</Days>");
+                then.status(200);
+            })
+            .await;
+        let policy = server
+            .mock_async(|when, then| {
+                when.method(DELETE)
+                    .path("/bucket/")
+                    .query_param_exists("policy");
+                then.status(204);
+            })
+            .await;
+        let client = client(&server, None);
+        client.set_versioning(true).await.unwrap();
+        let rules = [ExpirationRule {
+            id: "all".to_owned(),
+            days: Some(30),
+            ..Default::default()
+        }];
+        client.set_lifecycle(&rules).await.unwrap();
+        client.set_policy(None).await.unwrap();
+        versioning.assert_async().await;
+        lifecycle.assert_async().await;
+        policy.assert_async().await;
+    }
+}
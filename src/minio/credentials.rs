@@ -0,0 +1,565 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use s3::creds::{Credentials, Rfc3339OffsetDateTime};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use tokio::{
+    sync::mpsc::{channel, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use super::{Client, Error, Minio, Result};
+
+///refresh the credentials this long before their expiration
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const STS_VERSION: &str = "2011-06-15";
+const DEFAULT_SESSION_NAME: &str = "rs-utils";
+
+///Source of the credentials of the clients.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CredentialsSource {
+    ///the keys of the config
+    #[default]
+    Static,
+    ///`<prefix>_AWS_ACCESS_KEY`, `<prefix>_AWS_SECRET_KEY` and
+    ///`<prefix>_AWS_SESSION_TOKEN`, or the standard aws variables without
+    ///prefix
+    Env { prefix: Option<String> },
+    ///yaml or json file with the fields of the config keys, like a mounted
+    ///secret, reloaded when it changes
+    File { path: PathBuf },
+    ///token file exchanged with AssumeRoleWithWebIdentity on the sts
+    ///endpoint
+    WebIdentity {
+        endpoint: String,
+        role_arn: String,
+        token_file: PathBuf,
+        session_name: Option<String>,
+        ///lifetime of the credentials in seconds, chosen by the server if
+        ///unset
+        duration: Option<u32>,
+    },
+}
+
+///Source of credentials, called again before the credentials expire.
+#[async_trait]
+pub trait CredentialsProvider: fmt::Debug + Send + Sync {
+    async fn fetch(&self) -> Result<Credentials>;
+
+    ///file whose changes trigger a refresh
+    fn watched_file(&self) -> Option<&Path> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticProvider(pub Credentials);
+
+#[async_trait]
+impl CredentialsProvider for StaticProvider {
+    async fn fetch(&self) -> Result<Credentials> {
+        Ok(self.0.clone())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EnvProvider {
+    pub prefix: Option<String>,
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvProvider {
+    async fn fetch(&self) -> Result<Credentials> {
+        let names = match self.prefix {
+            Some(ref prefix) => [
+                format!("{prefix}_AWS_ACCESS_KEY"),
+                format!("{prefix}_AWS_SECRET_KEY"),
+                format!("{prefix}_AWS_SESSION_TOKEN"),
+            ],
+            None => [
+                "AWS_ACCESS_KEY_ID".to_owned(),
+                "AWS_SECRET_ACCESS_KEY".to_owned(),
+                "AWS_SESSION_TOKEN".to_owned(),
+            ],
+        };
+        let [access_key, secret_key, session_token] = names.map(|name| env::var(name).ok());
+        if access_key.is_none() || secret_key.is_none() {
+            return Err(Error::MissingCredentials);
+        }
+        Ok(Credentials {
+            access_key,
+            secret_key,
+            security_token: None,
+            session_token,
+            expiration: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileProvider {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl CredentialsProvider for FileProvider {
+    async fn fetch(&self) -> Result<Credentials> {
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    fn watched_file(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: Rfc3339OffsetDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StsResult {
+    credentials: StsCredentials,
+}
+
+#[derive(Debug, Deserialize)]
+struct StsResponse {
+    #[serde(rename = "AssumeRoleWithWebIdentityResult")]
+    result: StsResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebIdentityProvider {
+    ///url of the sts endpoint
+    pub endpoint: String,
+    pub role_arn: String,
+    ///file of the token, read again for each exchange as it is rotated
+    pub token_file: PathBuf,
+    pub session_name: String,
+    pub duration: Option<u32>,
+}
+
+#[async_trait]
+impl CredentialsProvider for WebIdentityProvider {
+    async fn fetch(&self) -> Result<Credentials> {
+        let token = tokio::fs::read_to_string(&self.token_file).await?;
+        let mut params = vec![
+            ("Action", "AssumeRoleWithWebIdentity".to_owned()),
+            ("Version", STS_VERSION.to_owned()),
+            ("RoleArn", self.role_arn.to_owned()),
+            ("RoleSessionName", self.session_name.to_owned()),
+            ("WebIdentityToken", token.trim().to_owned()),
+        ];
+        if let Some(duration) = self.duration {
+            params.push(("DurationSeconds", duration.to_string()));
+        }
+        let response = reqwest::Client::new()
+            .post(&self.endpoint)
+            .form(&params)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        if !(200..300).contains(&status) {
+            return Err(Error::Http(status, body));
+        }
+        let response: StsResponse =
+            quick_xml::de::from_str(&body).map_err(|e| Error::InvalidResponse(e.to_string()))?;
+        let credentials = response.result.credentials;
+        Ok(Credentials {
+            access_key: Some(credentials.access_key_id),
+            secret_key: Some(credentials.secret_access_key),
+            security_token: None,
+            session_token: Some(credentials.session_token),
+            expiration: Some(credentials.expiration),
+        })
+    }
+}
+
+///delay before the credentials expiring at `expiration` must be refreshed
+fn refresh_delay(expiration: &Rfc3339OffsetDateTime) -> Duration {
+    let remaining =
+        Duration::try_from(expiration.0 - OffsetDateTime::now_utc()).unwrap_or_default();
+    remaining
+        .saturating_sub(REFRESH_MARGIN)
+        .max(MIN_RETRY_DELAY)
+}
+
+///watch the directory of the file, as mounted secrets are replaced through
+///a symlink swap
+fn watch(path: &Path, tx: Sender<()>) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    let _ = tx.try_send(());
+                }
+            }
+        },
+        notify::Config::default(),
+    )?;
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    watcher.watch(dir.unwrap_or(Path::new(".")), RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+///wait for a change of the watched file, `None` if there is none
+async fn changed(rx: &mut Option<Receiver<()>>) -> Option<()> {
+    rx.as_mut()?.recv().await
+}
+
+///store the credentials without their expiration, rust-s3 would replace
+///them with the default credentials once expired
+fn store(shared: &RwLock<Credentials>, mut credentials: Credentials) {
+    credentials.expiration = None;
+    *shared.write().unwrap_or_else(|e| e.into_inner()) = credentials;
+}
+
+///refresh the credentials before they expire or when the watched file
+///change, retrying the failures until the task is aborted.
+///
+///The timer stops when the provider returns the same expiration again, as
+///for static credentials, only the file changes trigger a refresh then.
+async fn refresh(
+    shared: Arc<RwLock<Credentials>>,
+    provider: Arc<dyn CredentialsProvider>,
+    mut expiration: Option<Rfc3339OffsetDateTime>,
+    watcher: Option<(RecommendedWatcher, Receiver<()>)>,
+) {
+    let mut next = expiration.as_ref().map(refresh_delay);
+    let (_watcher, mut rx) = match watcher {
+        Some((watcher, rx)) => (Some(watcher), Some(rx)),
+        None if next.is_none() => return,
+        None => (None, None),
+    };
+    loop {
+        match next {
+            Some(delay) => tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                Some(()) = changed(&mut rx) => debug!("credentials file changed"),
+            },
+            None => {
+                if changed(&mut rx).await.is_none() {
+                    return;
+                }
+                debug!("credentials file changed");
+            }
+        }
+        let mut delay = MIN_RETRY_DELAY;
+        let credentials = loop {
+            match provider.fetch().await {
+                Ok(credentials) => break credentials,
+                Err(e) => {
+                    warn!("failed to refresh the credentials, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        };
+        if credentials.expiration.is_some() && credentials.expiration == expiration {
+            warn!("the refreshed credentials have the same expiration, not refreshing them again");
+            next = None;
+        } else {
+            expiration = credentials.expiration;
+            next = expiration.as_ref().map(refresh_delay);
+        }
+        store(&shared, credentials);
+        info!("credentials refreshed");
+    }
+}
+
+///Task keeping the credentials of a client up to date, stopped when
+///dropped.
+#[derive(Debug)]
+pub struct CredentialsRefresh {
+    task: JoinHandle<()>,
+}
+
+impl Drop for CredentialsRefresh {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    ///fetch the credentials of the provider and refresh them before they
+    ///expire, or when the file of the provider changes.
+    ///
+    ///The credentials are replaced in place, every client sharing them, as
+    ///the clones and the clients of [`Client::with_bucket`], use the new
+    ///ones.
+    pub async fn refresh_credentials(
+        &self,
+        provider: Arc<dyn CredentialsProvider>,
+    ) -> Result<CredentialsRefresh> {
+        //watch before fetching to not miss a change
+        let (tx, rx) = channel(1);
+        let watcher = match provider.watched_file().map(|path| watch(path, tx)) {
+            Some(Ok(watcher)) => Some((watcher, rx)),
+            Some(Err(e)) => {
+                warn!("failed to watch the credentials file: {e}");
+                None
+            }
+            None => None,
+        };
+        let credentials = provider.fetch().await?;
+        let expiration = credentials.expiration;
+        let shared = self.bucket.credentials.clone();
+        store(&shared, credentials);
+        let task = tokio::spawn(refresh(shared, provider, expiration, watcher));
+        Ok(CredentialsRefresh { task })
+    }
+}
+
+impl Minio {
+    ///provider of the configured credentials source
+    pub fn credentials_provider(&self) -> Arc<dyn CredentialsProvider> {
+        match self.credentials {
+            CredentialsSource::Static => Arc::new(StaticProvider(Credentials {
+                access_key: self.access_key.to_owned(),
+                secret_key: self.secret_key.to_owned(),
+                security_token: self.security_token.to_owned(),
+                session_token: self.session_token.to_owned(),
+                expiration: self.expiration,
+            })),
+            CredentialsSource::Env { ref prefix } => Arc::new(EnvProvider {
+                prefix: prefix.to_owned(),
+            }),
+            CredentialsSource::File { ref path } => Arc::new(FileProvider {
+                path: path.to_owned(),
+            }),
+            CredentialsSource::WebIdentity {
+                ref endpoint,
+                ref role_arn,
+                ref token_file,
+                ref session_name,
+                duration,
+            } => Arc::new(WebIdentityProvider {
+                endpoint: endpoint.to_owned(),
+                role_arn: role_arn.to_owned(),
+                token_file: token_file.to_owned(),
+                session_name: session_name
+                    .to_owned()
+                    .unwrap_or_else(|| DEFAULT_SESSION_NAME.to_owned()),
+                duration,
+            }),
+        }
+    }
+
+    ///fetch the credentials of the configured source for the clients and
+    ///keep them up to date, to call after `update`
+    pub async fn refresh_credentials(&mut self) -> Result<&mut Self> {
+        if self.client.is_none() && self.clients.is_empty() {
+            self.update()?;
+        }
+        let client = match self.client {
+            Some(ref client) => client,
+            None => self.clients.values().next().ok_or(Error::InvalidConfig(
+                "no bucket to refresh the credentials of",
+            ))?,
+        };
+        let refresh = client
+            .refresh_credentials(self.credentials_provider())
+            .await?;
+        self.refresh = Some(Arc::new(refresh));
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test_credentials {
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::testing::temp_dir;
+
+    fn access_key(client: &Client) -> Option<String> {
+        client.bucket.credentials.read().unwrap().access_key.clone()
+    }
+
+    #[test]
+    fn test_refresh_delay() {
+        let expiration = OffsetDateTime::now_utc() + time::Duration::hours(1);
+        let delay = refresh_delay(&expiration.into());
+        assert!(delay > Duration::from_secs(3200) && delay <= Duration::from_secs(3300));
+        let expiration = OffsetDateTime::now_utc() - time::Duration::hours(1);
+        assert_eq!(refresh_delay(&expiration.into()), MIN_RETRY_DELAY);
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingProvider {
+        fetches: std::sync::atomic::AtomicUsize,
+        expiration: Option<Rfc3339OffsetDateTime>,
+    }
+
+    #[async_trait]
+    impl CredentialsProvider for CountingProvider {
+        async fn fetch(&self) -> Result<Credentials> {
+            self.fetches
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Credentials {
+                access_key: Some("access".to_owned()),
+                secret_key: Some("secret".to_owned()),
+                security_token: None,
+                session_token: None,
+                expiration: self.expiration,
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_same_expiration() {
+        let expiration = OffsetDateTime::now_utc() - time::Duration::hours(1);
+        let provider = Arc::new(CountingProvider {
+            expiration: Some(expiration.into()),
+            ..Default::default()
+        });
+        let shared = Arc::new(RwLock::new(Credentials::anonymous().unwrap()));
+        let task = refresh(
+            shared.clone(),
+            provider.clone(),
+            Some(expiration.into()),
+            None,
+        );
+        tokio::time::timeout(Duration::from_secs(3600), task)
+            .await
+            .unwrap();
+        assert_eq!(
+            provider.fetches.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        let credentials = shared.read().unwrap().clone();
+        assert_eq!(credentials.access_key.as_deref(), Some("access"));
+        assert_eq!(credentials.expiration, None);
+    }
+
+    #[tokio::test]
+    async fn test_env_provider() {
+        env::set_var("TEST_ENV_PROVIDER_AWS_ACCESS_KEY", "access");
+        env::set_var("TEST_ENV_PROVIDER_AWS_SECRET_KEY", "secret");
+        let provider = EnvProvider {
+            prefix: Some("TEST_ENV_PROVIDER".to_owned()),
+        };
+        let credentials = provider.fetch().await.unwrap();
+        assert_eq!(credentials.access_key.as_deref(), Some("access"));
+        assert_eq!(credentials.session_token, None);
+        let provider = EnvProvider {
+            prefix: Some("TEST_ENV_PROVIDER_MISSING".to_owned()),
+        };
+        assert!(matches!(
+            provider.fetch().await,
+            Err(Error::MissingCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_file_provider_watch() {
+        let dir = temp_dir("credentials");
+        let path = dir.join("credentials.yaml");
+        std::fs::write(&path, "access_key: first\nsecret_key: secret\n").unwrap();
+        let config = Minio {
+            service: "http://localhost:9000".to_owned(),
+            name: "bucket".to_owned(),
+            credentials: CredentialsSource::File { path: path.clone() },
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        let _refresh = client
+            .refresh_credentials(config.credentials_provider())
+            .await
+            .unwrap();
+        assert_eq!(access_key(&client).as_deref(), Some("first"));
+        std::fs::write(&path, "access_key: second\nsecret_key: secret\n").unwrap();
+        for _ in 0..50 {
+            if access_key(&client).as_deref() == Some("second") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(access_key(&client).as_deref(), Some("second"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_without_client() {
+        let mut config = Minio {
+            service: "http://localhost:9000".to_owned(),
+            ..Default::default()
+        };
+        let res = config.refresh_credentials().await;
+        assert!(matches!(res, Err(Error::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_web_identity() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .body_contains("Action=AssumeRoleWithWebIdentity")
+                    .body_contains("WebIdentityToken=jwt")
+                    .body_contains("DurationSeconds=3600");
+                then.status(200).body(
+                    "<AssumeRoleWithWebIdentityResponse>\
+                    <AssumeRoleWithWebIdentityResult><Credentials>\
+                    <AccessKeyId>sts-access</AccessKeyId>\
+                    <SecretAccessKey>sts-secret</SecretAccessKey>\
+                    <SessionToken>token</SessionToken>\
+                    <Expiration>2099-01-01T00:00:00Z</Expiration>\
+                    </Credentials></AssumeRoleWithWebIdentityResult>\
+                    </AssumeRoleWithWebIdentityResponse>",
+                );
+            })
+            .await;
+        let dir = temp_dir("credentials");
+        let token_file = dir.join("token");
+        std::fs::write(&token_file, "jwt\n").unwrap();
+        let mut config = Minio {
+            service: "http://localhost:9000".to_owned(),
+            name: "bucket".to_owned(),
+            credentials: CredentialsSource::WebIdentity {
+                endpoint: server.base_url(),
+                role_arn: "arn:aws:iam::123:role/app".to_owned(),
+                token_file,
+                session_name: None,
+                duration: Some(3600),
+            },
+            buckets: [("logs".to_owned(), "logs".to_owned())].into(),
+            ..Default::default()
+        };
+        config
+            .update()
+            .unwrap()
+            .refresh_credentials()
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        let logs = config.bucket("logs").unwrap();
+        assert_eq!(access_key(logs).as_deref(), Some("sts-access"));
+        let credentials = logs.bucket.credentials.read().unwrap().clone();
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert!(credentials.expiration.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
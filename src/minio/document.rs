@@ -0,0 +1,242 @@
+#[cfg(feature = "minio-gzip")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "minio-gzip")]
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Client, Error, PutOptions, Result};
+
+const JSON: &str = "application/json";
+const YAML: &str = "application/yaml";
+
+///Compression of the stored documents, sent as the `Content-Encoding`.
+///
+///`Gzip` and `Zstd` need the `minio-gzip` and `minio-zstd` features, they
+///fail with [`Error::CompressionDisabled`] otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    fn from_encoding(encoding: Option<&str>) -> Result<Self> {
+        match encoding.map(str::trim) {
+            None | Some("") | Some("identity") => Ok(Compression::None),
+            Some("gzip") => Ok(Compression::Gzip),
+            Some("zstd") => Ok(Compression::Zstd),
+            Some(other) => Err(Error::InvalidResponse(format!(
+                "unsupported content encoding {other}"
+            ))),
+        }
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            #[cfg(feature = "minio-gzip")]
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "minio-zstd")]
+            Compression::Zstd => Ok(zstd::encode_all(data.as_slice(), 0)?),
+            #[allow(unreachable_patterns)]
+            other => Err(other.disabled()),
+        }
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            #[cfg(feature = "minio-gzip")]
+            Compression::Gzip => {
+                let mut decoded = Vec::new();
+                GzDecoder::new(data.as_slice()).read_to_end(&mut decoded)?;
+                Ok(decoded)
+            }
+            #[cfg(feature = "minio-zstd")]
+            Compression::Zstd => Ok(zstd::decode_all(data.as_slice())?),
+            #[allow(unreachable_patterns)]
+            other => Err(other.disabled()),
+        }
+    }
+
+    ///error of a compression whose feature is not enabled
+    #[cfg_attr(all(feature = "minio-gzip", feature = "minio-zstd"), allow(dead_code))]
+    fn disabled(&self) -> Error {
+        Error::CompressionDisabled(self.encoding().unwrap_or_default())
+    }
+}
+
+impl Client {
+    async fn put_document<S>(
+        &self,
+        path: S,
+        data: Vec<u8>,
+        content_type: &str,
+        compression: Compression,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let options = PutOptions {
+            content_type: Some(content_type.to_owned()),
+            content_encoding: compression.encoding().map(str::to_owned),
+            ..Default::default()
+        };
+        let data = compression.compress(data)?;
+        self.put_object_with(&data, path, &options).await?;
+        Ok(())
+    }
+
+    ///fetch the document, decompressed according to its content encoding
+    async fn get_document<S>(&self, path: S) -> Result<Vec<u8>>
+    where
+        S: AsRef<str>,
+    {
+        let response = self.get_object(path).await?;
+        let headers = response.headers();
+        let compression =
+            Compression::from_encoding(headers.get("content-encoding").map(String::as_str))?;
+        compression.decompress(response.to_vec())
+    }
+
+    ///store the value as json
+    pub async fn put_json<T, S>(&self, path: S, value: &T, compression: Compression) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+        S: AsRef<str>,
+    {
+        let data = serde_json::to_vec(value)?;
+        self.put_document(path, data, JSON, compression).await
+    }
+
+    pub async fn get_json<T, S>(&self, path: S) -> Result<T>
+    where
+        T: DeserializeOwned,
+        S: AsRef<str>,
+    {
+        Ok(serde_json::from_slice(&self.get_document(path).await?)?)
+    }
+
+    ///store the value as yaml
+    pub async fn put_yaml<T, S>(&self, path: S, value: &T, compression: Compression) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+        S: AsRef<str>,
+    {
+        let data = serde_yaml::to_string(value)?.into_bytes();
+        self.put_document(path, data, YAML, compression).await
+    }
+
+    pub async fn get_yaml<T, S>(&self, path: S) -> Result<T>
+    where
+        T: DeserializeOwned,
+        S: AsRef<str>,
+    {
+        Ok(serde_yaml::from_slice(&self.get_document(path).await?)?)
+    }
+}
+
+#[cfg(test)]
+mod test_document {
+    use std::collections::HashMap;
+
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::testing::client;
+
+    #[test]
+    #[cfg(all(feature = "minio-gzip", feature = "minio-zstd"))]
+    fn test_compression() {
+        let data = b"{\"a\":1}".repeat(100);
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(data.clone()).unwrap();
+            assert_eq!(compression.decompress(compressed).unwrap(), data);
+        }
+        assert!(Compression::from_encoding(Some("br")).is_err());
+        assert_eq!(
+            Compression::from_encoding(Some("gzip")).unwrap(),
+            Compression::Gzip
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "minio-zstd"))]
+    fn test_compression_disabled() {
+        let res = Compression::Zstd.compress(b"data".to_vec());
+        assert!(matches!(res, Err(Error::CompressionDisabled("zstd"))));
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "zstd compression requires the minio-zstd feature"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "minio-zstd")]
+    async fn test_put_json() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/doc.json")
+                    .header("content-type", "application/json")
+                    .header("content-encoding", "zstd");
+                then.status(200);
+            })
+            .await;
+        let value = HashMap::from([("a", 1)]);
+        client(&server)
+            .put_json("doc.json", &value, Compression::Zstd)
+            .await
+            .unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "minio-gzip")]
+    async fn test_get_json_gzip() {
+        let server = MockServer::start_async().await;
+        let body = Compression::Gzip.compress(b"{\"a\":1}".to_vec()).unwrap();
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/doc.json");
+                then.status(200)
+                    .header("content-encoding", "gzip")
+                    .body(body);
+            })
+            .await;
+        let value: HashMap<String, u32> = client(&server).get_json("doc.json").await.unwrap();
+        assert_eq!(value.get("a"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_get_yaml() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/doc.yaml");
+                then.status(200).body("a: 1\n");
+            })
+            .await;
+        let client = client(&server);
+        let value: HashMap<String, u32> = client.get_yaml("doc.yaml").await.unwrap();
+        assert_eq!(value.get("a"), Some(&1));
+        let res = client.get_json::<HashMap<String, u32>, _>("doc.yaml").await;
+        assert!(matches!(res, Err(Error::Json(_))));
+    }
+}
@@ -0,0 +1,164 @@
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
+use s3::{serde_types::Object, Bucket};
+
+use super::{Client, Result};
+
+///maximum number of keys of a listing page
+const MAX_KEYS: usize = 1000;
+
+///Stream of the entries of a bucket listing.
+pub type ListStream = BoxStream<'static, Result<ListEntry>>;
+
+///Entry of a bucket listing.
+#[derive(Debug, Clone)]
+pub enum ListEntry {
+    Object(Object),
+    ///common prefix of the keys grouped by the delimiter, like a folder
+    Prefix(String),
+}
+
+///Options of a bucket listing.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub prefix: String,
+    ///group the keys sharing a prefix up to the delimiter, usually `/`
+    pub delimiter: Option<String>,
+    ///list the keys after this one
+    pub start_after: Option<String>,
+    ///number of keys fetched per request, clamped between 1 and 1000
+    pub max_keys: Option<usize>,
+}
+
+impl ListOptions {
+    fn max_keys(&self) -> Option<usize> {
+        self.max_keys.map(|n| n.clamp(1, MAX_KEYS))
+    }
+}
+
+///state of the listing between two pages
+struct Pages {
+    bucket: Bucket,
+    options: ListOptions,
+    token: Option<String>,
+    done: bool,
+}
+
+impl Pages {
+    async fn next(mut self) -> Result<Option<(Vec<ListEntry>, Self)>> {
+        if self.done {
+            return Ok(None);
+        }
+        let start_after = match self.token {
+            Some(_) => None,
+            None => self.options.start_after.clone(),
+        };
+        let (page, _) = self
+            .bucket
+            .list_page(
+                self.options.prefix.clone(),
+                self.options.delimiter.clone(),
+                self.token.take(),
+                start_after,
+                self.options.max_keys(),
+            )
+            .await?;
+        self.token = page.next_continuation_token;
+        self.done = !page.is_truncated || self.token.is_none();
+        let prefixes = page.common_prefixes.unwrap_or_default();
+        let entries = page
+            .contents
+            .into_iter()
+            .map(ListEntry::Object)
+            .chain(prefixes.into_iter().map(|p| ListEntry::Prefix(p.prefix)))
+            .collect();
+        Ok(Some((entries, self)))
+    }
+}
+
+impl Client {
+    ///stream the objects and common prefixes of the bucket, fetching the
+    ///pages as the stream is consumed
+    pub fn list_entries(&self, options: ListOptions) -> ListStream {
+        let pages = Pages {
+            bucket: self.bucket.clone(),
+            options,
+            token: None,
+            done: false,
+        };
+        stream::try_unfold(pages, Pages::next)
+            .map_ok(|entries| stream::iter(entries.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test_list {
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::testing::client;
+
+    #[tokio::test]
+    async fn test_list_entries() {
+        let server = MockServer::start_async().await;
+        let first = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/")
+                    .query_param("list-type", "2")
+                    .query_param("delimiter", "/")
+                    .query_param("start-after", "a")
+                    .query_param("max-keys", "2");
+                then.status(200).body(
+                    "<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated>\
+                    <NextContinuationToken>next</NextContinuationToken>\
+                    <Contents><Key>b.txt</Key><LastModified>2024-01-01T00:00:00Z</LastModified>\
+                    <Size>3</Size></Contents>\
+                    <CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes></ListBucketResult>",
+                );
+            })
+            .await;
+        let second = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/")
+                    .query_param("continuation-token", "next");
+                then.status(200).body(
+                    "<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated>\
+                    <Contents><Key>c.txt</Key><LastModified>2024-01-01T00:00:00Z</LastModified>\
+                    <Size>5</Size></Contents></ListBucketResult>",
+                );
+            })
+            .await;
+        let options = ListOptions {
+            delimiter: Some("/".to_owned()),
+            start_after: Some("a".to_owned()),
+            max_keys: Some(2),
+            ..Default::default()
+        };
+        let mut entries = client(&server).list_entries(options);
+        assert!(matches!(entries.next().await, Some(Ok(ListEntry::Object(o))) if o.key == "b.txt"));
+        assert!(matches!(entries.next().await, Some(Ok(ListEntry::Prefix(p))) if p == "dir/"));
+        second.assert_hits_async(0).await;
+        assert!(matches!(entries.next().await, Some(Ok(ListEntry::Object(o))) if o.key == "c.txt"));
+        assert!(entries.next().await.is_none());
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let options = |max_keys| ListOptions {
+            max_keys,
+            ..Default::default()
+        };
+        assert_eq!(options(None).max_keys(), None);
+        assert_eq!(options(Some(0)).max_keys(), Some(1));
+        assert_eq!(options(Some(200)).max_keys(), Some(200));
+        assert_eq!(options(Some(5000)).max_keys(), Some(1000));
+    }
+}
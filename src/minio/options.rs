@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use reqwest::header::{
+    HeaderMap, HeaderName, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING,
+};
+use s3::{request::ResponseData, signing, Bucket};
+
+use super::{signed::header_value, Client, Error, Result};
+
+pub(crate) const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+const TAGGING: HeaderName = HeaderName::from_static("x-amz-tagging");
+
+///Headers and tags of an uploaded object.
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    ///`application/octet-stream` if unset
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub cache_control: Option<String>,
+    ///encoding of the content, like `gzip`
+    pub content_encoding: Option<String>,
+    ///user metadata, sent as `x-amz-meta-<key>` headers
+    pub metadata: HashMap<String, String>,
+    pub tags: HashMap<String, String>,
+}
+
+impl PutOptions {
+    pub(crate) fn content_type(&self) -> &str {
+        self.content_type.as_deref().unwrap_or(DEFAULT_CONTENT_TYPE)
+    }
+
+    ///headers of the options, the content type is sent by the commands
+    fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        if let Some(ref disposition) = self.content_disposition {
+            headers.insert(CONTENT_DISPOSITION, header_value(disposition)?);
+        }
+        if let Some(ref cache_control) = self.cache_control {
+            headers.insert(CACHE_CONTROL, header_value(cache_control)?);
+        }
+        if let Some(ref encoding) = self.content_encoding {
+            headers.insert(CONTENT_ENCODING, header_value(encoding)?);
+        }
+        for (key, value) in &self.metadata {
+            let name = format!("x-amz-meta-{}", key.to_lowercase());
+            let name =
+                HeaderName::try_from(name.as_str()).map_err(|_| Error::InvalidHeader(name))?;
+            headers.insert(name, header_value(value)?);
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|(k, v)| {
+                    format!(
+                        "{}={}",
+                        signing::uri_encode(k, true),
+                        signing::uri_encode(v, true)
+                    )
+                })
+                .collect();
+            headers.insert(TAGGING, header_value(&tags.join("&"))?);
+        }
+        Ok(headers)
+    }
+}
+
+impl Client {
+    ///bucket sending the headers of the options with each request
+    pub(crate) fn bucket_with(&self, options: &PutOptions) -> Result<Bucket> {
+        let mut headers = self.bucket.extra_headers.clone();
+        headers.extend(options.headers()?);
+        Ok(self.bucket.with_extra_headers(headers))
+    }
+
+    ///upload the object with the content type, headers, metadata and tags of
+    ///the options
+    pub async fn put_object_with<S>(
+        &self,
+        data: &[u8],
+        path: S,
+        options: &PutOptions,
+    ) -> Result<ResponseData>
+    where
+        S: AsRef<str>,
+    {
+        let bucket = self.bucket_with(options)?;
+        Ok(bucket
+            .put_object_with_content_type(path, data, options.content_type())
+            .await?)
+    }
+
+    ///user metadata of the object, without the `x-amz-meta-` prefix
+    pub async fn get_metadata<S>(&self, path: S) -> Result<HashMap<String, String>>
+    where
+        S: AsRef<str>,
+    {
+        Ok(self.head_object(path).await?.metadata)
+    }
+
+    pub async fn get_tags<S>(&self, path: S) -> Result<HashMap<String, String>>
+    where
+        S: AsRef<str>,
+    {
+        let (tags, _) = self.bucket.get_object_tagging(path).await?;
+        Ok(tags.into_iter().map(|t| (t.key(), t.value())).collect())
+    }
+
+    ///replace the tags of the object
+    pub async fn set_tags<S>(&self, path: S, tags: &HashMap<String, String>) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        self.bucket.put_object_tagging(path.as_ref(), &tags).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_options {
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::testing::client;
+
+    #[tokio::test]
+    async fn test_put_object_with() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/index.html")
+                    .header("content-type", "text/html")
+                    .header("cache-control", "max-age=60")
+                    .header("content-disposition", "inline")
+                    .header("x-amz-meta-owner", "alice")
+                    .header("x-amz-tagging", "env=prod%20eu");
+                then.status(200);
+            })
+            .await;
+        let options = PutOptions {
+            content_type: Some("text/html".to_owned()),
+            content_disposition: Some("inline".to_owned()),
+            cache_control: Some("max-age=60".to_owned()),
+            content_encoding: None,
+            metadata: HashMap::from([("Owner".to_owned(), "alice".to_owned())]),
+            tags: HashMap::from([("env".to_owned(), "prod eu".to_owned())]),
+        };
+        client(&server)
+            .put_object_with(b"<html/>", "index.html", &options)
+            .await
+            .unwrap();
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_invalid_metadata() {
+        let options = PutOptions {
+            metadata: HashMap::from([("in valid".to_owned(), "x".to_owned())]),
+            ..Default::default()
+        };
+        assert!(matches!(options.headers(), Err(Error::InvalidHeader(_))));
+    }
+
+    #[tokio::test]
+    async fn test_get_tags() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/file.txt")
+                    .query_param_exists("tagging");
+                then.status(200).body(
+                    "<Tagging><TagSet><Tag><Key>env</Key><Value>prod</Value></Tag></TagSet></Tagging>",
+                );
+            })
+            .await;
+        let tags = client(&server).get_tags("file.txt").await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(tags, HashMap::from([("env".to_owned(), "prod".to_owned())]));
+    }
+}
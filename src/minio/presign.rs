@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use reqwest::Method;
+
+use super::{signed::SignedRequest, Client, Result};
+
+impl Client {
+    fn presign<S>(
+        &self,
+        method: Method,
+        path: S,
+        expiry: Duration,
+        queries: &[(&str, &str)],
+        content_type: Option<&str>,
+    ) -> Result<String>
+    where
+        S: AsRef<str>,
+    {
+        let mut request = SignedRequest::new(&self.bucket, method, path.as_ref());
+        for (key, value) in queries {
+            request = request.query(key, value);
+        }
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type)?;
+        }
+        let expiry = u32::try_from(expiry.as_secs()).unwrap_or(u32::MAX);
+        request.presign(expiry)
+    }
+
+    ///url to download the object without credentials for `expiry`, at most
+    ///a week. The queries are added to the signed url, like
+    ///`response-content-disposition`.
+    pub fn presign_get<S>(
+        &self,
+        path: S,
+        expiry: Duration,
+        queries: &[(&str, &str)],
+    ) -> Result<String>
+    where
+        S: AsRef<str>,
+    {
+        self.presign(Method::GET, path, expiry, queries, None)
+    }
+
+    ///url to upload the object without credentials for `expiry`, at most a
+    ///week. With a content type the upload must be sent with this
+    ///`Content-Type` header.
+    pub fn presign_put<S>(
+        &self,
+        path: S,
+        expiry: Duration,
+        content_type: Option<&str>,
+        queries: &[(&str, &str)],
+    ) -> Result<String>
+    where
+        S: AsRef<str>,
+    {
+        self.presign(Method::PUT, path, expiry, queries, content_type)
+    }
+
+    ///url to delete the object without credentials for `expiry`, at most a
+    ///week
+    pub fn presign_delete<S>(
+        &self,
+        path: S,
+        expiry: Duration,
+        queries: &[(&str, &str)],
+    ) -> Result<String>
+    where
+        S: AsRef<str>,
+    {
+        self.presign(Method::DELETE, path, expiry, queries, None)
+    }
+}
+
+#[cfg(test)]
+mod test_presign {
+    use super::*;
+    use crate::minio::{Error, Minio};
+
+    fn client() -> Client {
+        let config = Minio {
+            service: "http://localhost:9000".to_owned(),
+            name: "bucket".to_owned(),
+            access_key: Some("access".to_owned()),
+            secret_key: Some("secret".to_owned()),
+            ..Default::default()
+        };
+        Client::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_presign_get() {
+        let url = client()
+            .presign_get(
+                "dir/file name.txt",
+                Duration::from_secs(3600),
+                &[("response-content-disposition", "attachment")],
+            )
+            .unwrap();
+        assert!(url.starts_with("http://localhost:9000/bucket/dir/file%20name.txt?"));
+        assert!(url.contains("response-content-disposition=attachment"));
+        assert!(url.contains("X-Amz-Expires=3600"));
+        assert!(url.contains("X-Amz-SignedHeaders=host&"));
+    }
+
+    #[test]
+    fn test_presign_put_content_type() {
+        let url = client()
+            .presign_put(
+                "file.json",
+                Duration::from_secs(60),
+                Some("application/json"),
+                &[],
+            )
+            .unwrap();
+        assert!(url.contains("X-Amz-SignedHeaders=content-type%3Bhost"));
+    }
+
+    #[test]
+    fn test_presign_invalid_expiry() {
+        let client = client();
+        let res = client.presign_delete("file.txt", Duration::from_secs(8 * 86400), &[]);
+        assert!(matches!(res, Err(Error::S3(_))));
+        let config = Minio {
+            service: "http://localhost:9000".to_owned(),
+            name: "bucket".to_owned(),
+            ..Default::default()
+        };
+        let res =
+            Client::new(&config)
+                .unwrap()
+                .presign_delete("file.txt", Duration::from_secs(60), &[]);
+        assert!(matches!(res, Err(Error::MissingCredentials)));
+    }
+}
@@ -0,0 +1,498 @@
+use bytes::Buf;
+use futures::Stream;
+use log::{debug, warn};
+use reqwest::Method;
+use s3::{serde_types::Part, Bucket};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+use super::{
+    options::{PutOptions, DEFAULT_CONTENT_TYPE},
+    signed::SignedRequest,
+    Client, Error, Result,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedPart {
+    part_number: u32,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListPartsResult {
+    #[serde(default, rename = "Part")]
+    parts: Vec<ListedPart>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_part_number_marker: Option<u32>,
+}
+
+///initial capacity of the read buffers, grown as the bytes arrive
+const READ_CAPACITY: usize = 64 * 1024;
+
+///read up to `size` bytes, less only at the end of the reader
+pub(super) async fn read_part<R>(reader: &mut R, size: usize) -> Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut part = Vec::with_capacity(size.min(READ_CAPACITY));
+    (&mut *reader)
+        .take(size as u64)
+        .read_to_end(&mut part)
+        .await?;
+    Ok(part)
+}
+
+///Multipart upload in progress.
+///
+///The upload id can be saved to resume the session with
+///[`Client::resume_multipart`] after a failure, the parts already uploaded
+///are kept by the server until the upload is completed or aborted.
+#[derive(Debug, Clone)]
+pub struct MultipartSession {
+    bucket: Bucket,
+    path: String,
+    upload_id: String,
+    part_size: usize,
+    parts: Vec<Part>,
+    uploaded: u64,
+}
+
+impl MultipartSession {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    ///number of bytes already uploaded, the offset to resume the source at
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    ///upload the next part, all the parts but the last one must be at
+    ///least 5 MiB
+    pub async fn put_part(&mut self, data: Vec<u8>) -> Result<()> {
+        let number = self.parts.last().map_or(1, |p| p.part_number + 1);
+        let size = data.len() as u64;
+        let part = self
+            .bucket
+            .put_multipart_chunk(
+                data,
+                &self.path,
+                number,
+                &self.upload_id,
+                DEFAULT_CONTENT_TYPE,
+            )
+            .await?;
+        debug!("uploaded part {number} of {}", self.path);
+        self.parts.push(part);
+        self.uploaded += size;
+        Ok(())
+    }
+
+    ///upload the reader until its end as the next parts, return the number
+    ///of bytes uploaded.
+    ///
+    ///When resuming a session the reader must start at [`Self::uploaded`].
+    pub async fn upload<R>(&mut self, reader: &mut R) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let start = self.uploaded;
+        loop {
+            let part = read_part(reader, self.part_size).await?;
+            if part.is_empty() {
+                break;
+            }
+            let last = part.len() < self.part_size;
+            self.put_part(part).await?;
+            if last {
+                break;
+            }
+        }
+        Ok(self.uploaded - start)
+    }
+
+    ///assemble the uploaded parts into the object
+    pub async fn complete(self) -> Result<()> {
+        self.bucket
+            .complete_multipart_upload(&self.path, &self.upload_id, self.parts)
+            .await?;
+        Ok(())
+    }
+
+    ///upload the reader and complete the upload, abort it on failure
+    async fn upload_all<R>(mut self, reader: &mut R) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let res = match self.upload(reader).await {
+            Ok(_) => self
+                .bucket
+                .complete_multipart_upload(&self.path, &self.upload_id, self.parts.clone())
+                .await
+                .map_err(Error::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!("multipart upload of {} failed, aborting: {e}", self.path);
+            if let Err(abort) = self.abort().await {
+                warn!("failed to abort the multipart upload: {abort}");
+            }
+            return Err(e);
+        }
+        Ok(self.uploaded)
+    }
+
+    ///abort the upload, the server drop the uploaded parts
+    pub async fn abort(self) -> Result<()> {
+        Ok(self
+            .bucket
+            .abort_upload(&self.path, &self.upload_id)
+            .await?)
+    }
+}
+
+impl Client {
+    ///start a multipart upload of the object
+    pub async fn start_multipart<S>(&self, path: S) -> Result<MultipartSession>
+    where
+        S: AsRef<str>,
+    {
+        self.start_multipart_with(path, &PutOptions::default())
+            .await
+    }
+
+    ///start a multipart upload of the object with the headers, metadata and
+    ///tags of the options
+    pub async fn start_multipart_with<S>(
+        &self,
+        path: S,
+        options: &PutOptions,
+    ) -> Result<MultipartSession>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        let response = self
+            .bucket_with(options)?
+            .initiate_multipart_upload(path, options.content_type())
+            .await?;
+        Ok(MultipartSession {
+            bucket: self.bucket.clone(),
+            path: path.to_owned(),
+            upload_id: response.upload_id,
+            part_size: self.part_size,
+            parts: Vec::new(),
+            uploaded: 0,
+        })
+    }
+
+    ///resume a multipart upload, fetching the parts already uploaded
+    pub async fn resume_multipart<S>(&self, path: S, upload_id: &str) -> Result<MultipartSession>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        let mut session = MultipartSession {
+            bucket: self.bucket.clone(),
+            path: path.to_owned(),
+            upload_id: upload_id.to_owned(),
+            part_size: self.part_size,
+            parts: Vec::new(),
+            uploaded: 0,
+        };
+        let mut marker = None;
+        loop {
+            let mut request =
+                SignedRequest::new(&self.bucket, Method::GET, path).query("uploadId", upload_id);
+            if let Some(marker) = marker {
+                request = request.query("part-number-marker", &format!("{marker}"));
+            }
+            let response = request.send(&self.http).await?;
+            let result: ListPartsResult = quick_xml::de::from_reader(response.body.as_slice())
+                .map_err(|e| Error::InvalidResponse(e.to_string()))?;
+            for part in result.parts {
+                session.uploaded += part.size;
+                session.parts.push(Part {
+                    part_number: part.part_number,
+                    etag: part.etag,
+                });
+            }
+            match result.next_part_number_marker {
+                Some(next) if result.is_truncated => marker = Some(next),
+                _ => break,
+            }
+        }
+        Ok(session)
+    }
+
+    ///upload the reader, with a multipart upload if it is larger than the
+    ///multipart threshold. Return the number of bytes uploaded.
+    ///
+    ///A failed multipart upload is aborted, including when its completion
+    ///fails.
+    pub async fn put_object_stream<R, S>(&self, reader: &mut R, path: S) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        S: AsRef<str>,
+    {
+        self.put_object_stream_with(reader, path, &PutOptions::default())
+            .await
+    }
+
+    ///upload the reader with the options, see [`Client::put_object_stream`]
+    pub async fn put_object_stream_with<R, S>(
+        &self,
+        reader: &mut R,
+        path: S,
+        options: &PutOptions,
+    ) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        S: AsRef<str>,
+    {
+        let first = read_part(reader, self.multipart_threshold.saturating_add(1)).await?;
+        if first.len() <= self.multipart_threshold {
+            self.put_object_with(&first, path, options).await?;
+            return Ok(first.len() as u64);
+        }
+        self.start_multipart_with(path, options)
+            .await?
+            .upload_all(&mut AsyncReadExt::chain(first.as_slice(), reader))
+            .await
+    }
+
+    ///upload a stream of bytes, see [`Client::put_object_stream`]
+    pub async fn put_object_from_stream<T, B, E, S>(&self, stream: T, path: S) -> Result<u64>
+    where
+        T: Stream<Item = std::result::Result<B, E>> + Unpin,
+        B: Buf,
+        E: Into<std::io::Error>,
+        S: AsRef<str>,
+    {
+        let mut reader = StreamReader::new(stream);
+        self.put_object_stream(&mut reader, path).await
+    }
+
+    ///download the object into the writer without buffering it
+    pub async fn get_object_to_writer<W, S>(&self, path: S, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin,
+        S: AsRef<str>,
+    {
+        self.bucket.get_object_to_writer(path, writer).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_stream {
+    use futures::stream;
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::{testing, Minio, MIN_PART_SIZE};
+
+    fn client(server: &MockServer) -> Client {
+        let config = Minio {
+            multipart_threshold: Some(16),
+            part_size: Some(MIN_PART_SIZE),
+            ..testing::config(server)
+        };
+        Client::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_put_small_object_stream() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT).path("/bucket/small.txt").body("hello");
+                then.status(200);
+            })
+            .await;
+        let chunks = stream::iter(vec![Ok::<_, std::io::Error>(&b"hel"[..]), Ok(&b"lo"[..])]);
+        let size = client(&server)
+            .put_object_from_stream(chunks, "small.txt")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        assert_eq!(size, 5);
+    }
+
+    #[tokio::test]
+    async fn test_put_multipart_object_stream() {
+        let server = MockServer::start_async().await;
+        let initiate = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/big")
+                    .query_param_exists("uploads");
+                then.status(200).body(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                    <Key>big</Key><UploadId>42</UploadId></InitiateMultipartUploadResult>",
+                );
+            })
+            .await;
+        let parts = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42")
+                    .query_param_exists("partNumber");
+                then.status(200).header("etag", "\"part\"");
+            })
+            .await;
+        let complete = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42")
+                    .body_contains("<PartNumber>2</PartNumber>");
+                then.status(200).body("<CompleteMultipartUploadResult/>");
+            })
+            .await;
+        let data = vec![7u8; MIN_PART_SIZE + 10];
+        let size = client(&server)
+            .put_object_stream(&mut data.as_slice(), "big")
+            .await
+            .unwrap();
+        initiate.assert_async().await;
+        parts.assert_hits_async(2).await;
+        complete.assert_async().await;
+        assert_eq!(size, data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_put_multipart_abort() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/big")
+                    .query_param_exists("uploads");
+                then.status(200).body(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                    <Key>big</Key><UploadId>42</UploadId></InitiateMultipartUploadResult>",
+                );
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(PUT).path("/bucket/big");
+                then.status(500);
+            })
+            .await;
+        let abort = server
+            .mock_async(|when, then| {
+                when.method(DELETE)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42");
+                then.status(204);
+            })
+            .await;
+        let data = vec![7u8; MIN_PART_SIZE];
+        let res = client(&server)
+            .put_object_stream(&mut data.as_slice(), "big")
+            .await;
+        assert!(matches!(res, Err(Error::Http(500, _))));
+        abort.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_put_multipart_complete_abort() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/big")
+                    .query_param_exists("uploads");
+                then.status(200).body(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                    <Key>big</Key><UploadId>42</UploadId></InitiateMultipartUploadResult>",
+                );
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42");
+                then.status(200).header("etag", "\"part\"");
+            })
+            .await;
+        let complete = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42");
+                then.status(500);
+            })
+            .await;
+        let abort = server
+            .mock_async(|when, then| {
+                when.method(DELETE)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42");
+                then.status(204);
+            })
+            .await;
+        let data = vec![7u8; 64];
+        let res = client(&server)
+            .put_object_stream(&mut data.as_slice(), "big")
+            .await;
+        assert!(matches!(res, Err(Error::Http(500, _))));
+        complete.assert_async().await;
+        abort.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_multipart() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/big")
+                    .query_param("uploadId", "42");
+                then.status(200).body(
+                    "<ListPartsResult><IsTruncated>false</IsTruncated>\
+                    <Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag><Size>5242880</Size></Part>\
+                    <Part><PartNumber>2</PartNumber><ETag>\"b\"</ETag><Size>5242880</Size></Part>\
+                    </ListPartsResult>",
+                );
+            })
+            .await;
+        let session = client(&server).resume_multipart("big", "42").await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(session.upload_id(), "42");
+        assert_eq!(session.uploaded(), 2 * 5242880);
+        assert_eq!(session.parts.last().unwrap().etag, "\"b\"");
+    }
+
+    #[tokio::test]
+    async fn test_get_object_to_writer() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/file.txt");
+                then.status(200).body("content");
+            })
+            .await;
+        let mut writer = Vec::new();
+        client(&server)
+            .get_object_to_writer("file.txt", &mut writer)
+            .await
+            .unwrap();
+        assert_eq!(writer, b"content");
+    }
+}
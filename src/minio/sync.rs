@@ -0,0 +1,664 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use log::{debug, warn};
+use tokio::fs::{self, File};
+
+use super::{
+    list::{ListEntry, ListOptions},
+    stream::read_part,
+    Client, DeleteError, Error, Result,
+};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+///Callback of the progress of a synchronization.
+pub type SyncProgress = Arc<dyn Fn(&SyncEvent) + Send + Sync>;
+
+///Options of a directory synchronization.
+#[derive(Clone, Default)]
+pub struct SyncOptions {
+    ///delete the objects or the files missing from the source
+    pub delete: bool,
+    ///number of concurrent transfers, 4 if unset
+    pub concurrency: Option<usize>,
+    ///called after each file transferred, skipped or deleted
+    pub progress: Option<SyncProgress>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    Uploaded,
+    Downloaded,
+    ///the size and etag of the source and the destination match
+    Skipped,
+    Deleted,
+}
+
+///Progress of a synchronization.
+#[derive(Debug, Clone)]
+pub struct SyncEvent {
+    ///path relative to the directory and the prefix
+    pub key: String,
+    pub action: SyncAction,
+    ///bytes transferred for this key
+    pub bytes: u64,
+    ///number of keys processed, including this one
+    pub done: usize,
+    pub total: usize,
+}
+
+///Summary of a synchronization.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    pub transferred: usize,
+    pub skipped: usize,
+    pub deleted: usize,
+    pub bytes: u64,
+    ///objects that could not be deleted
+    pub delete_errors: Vec<DeleteError>,
+}
+
+///size and etag of an object
+struct Remote {
+    size: u64,
+    etag: String,
+}
+
+///size and path of a file
+struct Local {
+    size: u64,
+    path: PathBuf,
+}
+
+///key, action and bytes of a transfer, `None` if it was not started
+///after a failure
+type Transfer = Option<(String, SyncAction, u64)>;
+
+struct Progress<'a> {
+    callback: Option<&'a SyncProgress>,
+    report: SyncReport,
+    done: usize,
+    total: usize,
+}
+
+impl<'a> Progress<'a> {
+    fn new(options: &'a SyncOptions, total: usize) -> Self {
+        Progress {
+            callback: options.progress.as_ref(),
+            report: SyncReport::default(),
+            done: 0,
+            total,
+        }
+    }
+
+    fn record(&mut self, key: String, action: SyncAction, bytes: u64) {
+        match action {
+            SyncAction::Uploaded | SyncAction::Downloaded => self.report.transferred += 1,
+            SyncAction::Skipped => self.report.skipped += 1,
+            SyncAction::Deleted => self.report.deleted += 1,
+        }
+        self.report.bytes += bytes;
+        self.done += 1;
+        debug!("{key}: {action:?}");
+        if let Some(callback) = self.callback {
+            callback(&SyncEvent {
+                key,
+                action,
+                bytes,
+                done: self.done,
+                total: self.total,
+            });
+        }
+    }
+
+    ///record the transfers as they complete. After a failure the transfers
+    ///in flight are awaited rather than cancelled, so no multipart upload
+    ///or file is left half written, and the first error is returned.
+    async fn drain<S>(&mut self, mut transfers: S, failed: &AtomicBool) -> Result<()>
+    where
+        S: Stream<Item = Result<Transfer>> + Unpin,
+    {
+        let mut error = None;
+        while let Some(res) = transfers.next().await {
+            match res {
+                Ok(Some((key, action, bytes))) => self.record(key, action, bytes),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("transfer failed: {e}");
+                    failed.store(true, Ordering::Relaxed);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+///prefix ending with a `/`, unless empty
+fn normalize_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim_start_matches('/');
+    if prefix.is_empty() || prefix.ends_with('/') {
+        prefix.to_owned()
+    } else {
+        format!("{prefix}/")
+    }
+}
+
+///path of the key in the directory, refusing the keys escaping it
+fn local_path(dir: &Path, key: &str) -> Result<PathBuf> {
+    let relative = Path::new(key);
+    let valid = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !valid {
+        return Err(Error::InvalidResponse(format!("invalid key {key}")));
+    }
+    Ok(dir.join(relative))
+}
+
+///temporary file next to the path, renamed over it once complete
+fn download_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.download"))
+}
+
+///remove the parents of the path left empty, up to the directory
+async fn remove_empty_parents(dir: &Path, path: &Path) {
+    let mut current = path.parent();
+    while let Some(parent) = current.filter(|p| *p != dir && p.starts_with(dir)) {
+        if fs::remove_dir(parent).await.is_err() {
+            break;
+        }
+        current = parent.parent();
+    }
+}
+
+///files of the directory and its subdirectories
+async fn local_files(dir: &Path) -> Result<HashMap<String, Local>> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(current) = dirs.pop() {
+        let mut entries = fs::read_dir(&current).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let key: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let key = key.join("/");
+            let local = Local {
+                size: metadata.len(),
+                path,
+            };
+            files.insert(key, local);
+        }
+    }
+    Ok(files)
+}
+
+///etag of the file as computed by the server, with the part size of the
+///multipart uploads if `multipart` is set
+async fn local_etag(path: &Path, part_size: usize, multipart: bool) -> Result<String> {
+    let mut file = File::open(path).await?;
+    if !multipart {
+        let mut context = md5::Context::new();
+        loop {
+            let chunk = read_part(&mut file, part_size).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            context.consume(&chunk);
+        }
+        return Ok(format!("{:x}", context.compute()));
+    }
+    let mut digests = Vec::new();
+    let mut count = 0;
+    loop {
+        let chunk = read_part(&mut file, part_size).await?;
+        if chunk.is_empty() {
+            break;
+        }
+        digests.extend_from_slice(&md5::compute(&chunk).0);
+        count += 1;
+    }
+    Ok(format!("{:x}-{count}", md5::compute(&digests)))
+}
+
+impl Client {
+    ///objects of the prefix by key relative to it
+    async fn remote_objects(&self, prefix: &str) -> Result<HashMap<String, Remote>> {
+        let options = ListOptions {
+            prefix: prefix.to_owned(),
+            ..Default::default()
+        };
+        let entries: Vec<ListEntry> = self.list_entries(options).try_collect().await?;
+        let objects = entries
+            .into_iter()
+            .filter_map(|entry| match entry {
+                ListEntry::Object(object) => Some(object),
+                ListEntry::Prefix(_) => None,
+            })
+            .filter(|object| !object.key.ends_with('/'))
+            .map(|object| {
+                let key = object.key[prefix.len()..].to_owned();
+                let remote = Remote {
+                    size: object.size,
+                    etag: object
+                        .e_tag
+                        .unwrap_or_default()
+                        .trim_matches('"')
+                        .to_owned(),
+                };
+                (key, remote)
+            })
+            .collect();
+        Ok(objects)
+    }
+
+    ///return true if the file has the size and the etag of the object
+    async fn unchanged(&self, local: &Local, remote: &Remote) -> Result<bool> {
+        if local.size != remote.size {
+            return Ok(false);
+        }
+        //the parts of another part size can not match, skip the hashing
+        let multipart = match remote.etag.split_once('-') {
+            Some((_, parts)) => match parts.parse::<u64>() {
+                Ok(parts) if parts == local.size.div_ceil(self.part_size as u64).max(1) => true,
+                _ => return Ok(false),
+            },
+            None => false,
+        };
+        Ok(local_etag(&local.path, self.part_size, multipart).await? == remote.etag)
+    }
+
+    ///upload the files of the directory missing or different in the
+    ///prefix, and delete the objects missing from the directory with the
+    ///`delete` option.
+    ///
+    ///The files are compared with their size and etag, the etag of the
+    ///objects uploaded with another multipart part size never match.
+    pub async fn sync_to_bucket<P>(
+        &self,
+        dir: P,
+        prefix: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncReport>
+    where
+        P: AsRef<Path>,
+    {
+        let prefix = normalize_prefix(prefix);
+        let local = local_files(dir.as_ref()).await?;
+        let remote = self.remote_objects(&prefix).await?;
+        let extra: Vec<&String> = if options.delete {
+            remote.keys().filter(|k| !local.contains_key(*k)).collect()
+        } else {
+            Vec::new()
+        };
+        let mut progress = Progress::new(options, local.len() + extra.len());
+        let concurrency = options.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1);
+        let failed = AtomicBool::new(false);
+        let transfers = stream::iter(&local)
+            .map(|(key, file)| {
+                let remote = remote.get(key);
+                let prefix = &prefix;
+                let failed = &failed;
+                async move {
+                    if failed.load(Ordering::Relaxed) {
+                        return Ok(None);
+                    }
+                    if let Some(remote) = remote {
+                        if self.unchanged(file, remote).await? {
+                            return Ok(Some((key.to_owned(), SyncAction::Skipped, 0)));
+                        }
+                    }
+                    //a failed multipart upload is aborted by put_object_stream
+                    let mut reader = File::open(&file.path).await?;
+                    let size = self
+                        .put_object_stream(&mut reader, format!("{prefix}{key}"))
+                        .await?;
+                    Ok(Some((key.to_owned(), SyncAction::Uploaded, size)))
+                }
+            })
+            .buffer_unordered(concurrency);
+        progress.drain(transfers, &failed).await?;
+        if !extra.is_empty() {
+            let paths: Vec<String> = extra.iter().map(|key| format!("{prefix}{key}")).collect();
+            let errors = self.delete_objects(&paths).await?;
+            for key in extra {
+                let path = format!("{prefix}{key}");
+                if errors.iter().all(|e| e.key != path) {
+                    progress.record(key.to_owned(), SyncAction::Deleted, 0);
+                }
+            }
+            if !errors.is_empty() {
+                warn!("failed to delete {} objects of {prefix}", errors.len());
+            }
+            progress.report.delete_errors = errors;
+        }
+        Ok(progress.report)
+    }
+
+    ///download the objects of the prefix missing or different in the
+    ///directory, and delete the files missing from the prefix with the
+    ///`delete` option
+    pub async fn sync_from_bucket<P>(
+        &self,
+        prefix: &str,
+        dir: P,
+        options: &SyncOptions,
+    ) -> Result<SyncReport>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        let prefix = normalize_prefix(prefix);
+        fs::create_dir_all(dir).await?;
+        let remote = self.remote_objects(&prefix).await?;
+        let local = local_files(dir).await?;
+        let extra: Vec<(&String, &Local)> = match options.delete {
+            true => local
+                .iter()
+                .filter(|(k, _)| !remote.contains_key(*k))
+                .collect(),
+            false => Vec::new(),
+        };
+        let mut progress = Progress::new(options, remote.len() + extra.len());
+        let concurrency = options.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1);
+        let failed = AtomicBool::new(false);
+        let transfers = stream::iter(&remote)
+            .map(|(key, object)| {
+                let file = local.get(key);
+                let prefix = &prefix;
+                let failed = &failed;
+                async move {
+                    if failed.load(Ordering::Relaxed) {
+                        return Ok(None);
+                    }
+                    if let Some(file) = file {
+                        if self.unchanged(file, object).await? {
+                            return Ok(Some((key.to_owned(), SyncAction::Skipped, 0)));
+                        }
+                    }
+                    let path = local_path(dir, key)?;
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).await?;
+                    }
+                    //download aside to keep the current file if it fails
+                    let download = download_path(&path);
+                    let mut writer = File::create(&download).await?;
+                    let res = self
+                        .get_object_to_writer(format!("{prefix}{key}"), &mut writer)
+                        .await;
+                    drop(writer);
+                    let res = match res {
+                        Ok(()) => fs::rename(&download, &path).await.map_err(Error::from),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = res {
+                        if let Err(remove) = fs::remove_file(&download).await {
+                            warn!("failed to remove {}: {remove}", download.display());
+                        }
+                        return Err(e);
+                    }
+                    Ok(Some((key.to_owned(), SyncAction::Downloaded, object.size)))
+                }
+            })
+            .buffer_unordered(concurrency);
+        progress.drain(transfers, &failed).await?;
+        for (key, file) in extra {
+            fs::remove_file(&file.path).await?;
+            remove_empty_parents(dir, &file.path).await;
+            progress.record(key.to_owned(), SyncAction::Deleted, 0);
+        }
+        Ok(progress.report)
+    }
+}
+
+#[cfg(test)]
+mod test_sync {
+    use std::sync::Mutex;
+
+    use httpmock::prelude::*;
+
+    use super::*;
+    use crate::minio::testing::{client, temp_dir};
+
+    fn listing(objects: &[(&str, &str, u64)]) -> String {
+        let contents: String = objects
+            .iter()
+            .map(|(key, etag, size)| {
+                format!(
+                    "<Contents><Key>{key}</Key><ETag>\"{etag}\"</ETag><Size>{size}</Size>\
+                    <LastModified>2024-01-01T00:00:00Z</LastModified></Contents>"
+                )
+            })
+            .collect();
+        format!(
+            "<ListBucketResult><Name>bucket</Name><IsTruncated>false</IsTruncated>{contents}\
+            </ListBucketResult>"
+        )
+    }
+
+    #[test]
+    fn test_local_path() {
+        let dir = Path::new("/data");
+        assert_eq!(
+            local_path(dir, "a/b.txt").unwrap(),
+            Path::new("/data/a/b.txt")
+        );
+        assert!(local_path(dir, "../etc/passwd").is_err());
+        assert!(local_path(dir, "/etc/passwd").is_err());
+        assert_eq!(normalize_prefix("/out"), "out/");
+        assert_eq!(normalize_prefix(""), "");
+    }
+
+    #[tokio::test]
+    async fn test_local_etag() {
+        let dir = temp_dir("sync");
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let etag = local_etag(&path, 4, false).await.unwrap();
+        assert_eq!(etag, format!("{:x}", md5::compute(b"0123456789")));
+        let digests = [b"0123".as_slice(), b"4567", b"89"]
+            .iter()
+            .flat_map(|part| md5::compute(part).0)
+            .collect::<Vec<u8>>();
+        let expected = format!("{:x}-3", md5::compute(digests));
+        assert_eq!(local_etag(&path, 4, true).await.unwrap(), expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_to_bucket() {
+        let dir = temp_dir("sync");
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "world").unwrap();
+        let server = MockServer::start_async().await;
+        let objects = [
+            ("out/a.txt", "5d41402abc4b2a76b9719d911017c592", 5),
+            ("out/old.txt", "0", 1),
+        ];
+        server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/")
+                    .query_param("prefix", "out/");
+                then.status(200).body(listing(&objects));
+            })
+            .await;
+        let upload = server
+            .mock_async(|when, then| {
+                when.method(PUT).path("/bucket/out/sub/b.txt").body("world");
+                then.status(200);
+            })
+            .await;
+        let delete = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/bucket/")
+                    .query_param_exists("delete")
+                    .body_contains("<Key>out/old.txt</Key>");
+                then.status(200).body("<DeleteResult/>");
+            })
+            .await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let options = SyncOptions {
+            delete: true,
+            concurrency: Some(2),
+            progress: Some(Arc::new(move |event: &SyncEvent| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((event.key.clone(), event.action));
+            })),
+        };
+        let report = client(&server)
+            .sync_to_bucket(&dir, "out", &options)
+            .await
+            .unwrap();
+        upload.assert_async().await;
+        delete.assert_async().await;
+        assert_eq!(report.transferred, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.deleted, 1);
+        assert_eq!(report.bytes, 5);
+        let mut events = events.lock().unwrap().clone();
+        events.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected = vec![
+            ("a.txt".to_owned(), SyncAction::Skipped),
+            ("old.txt".to_owned(), SyncAction::Deleted),
+            ("sub/b.txt".to_owned(), SyncAction::Uploaded),
+        ];
+        assert_eq!(events, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_bucket() {
+        let dir = temp_dir("sync");
+        std::fs::create_dir_all(dir.join("old/nested")).unwrap();
+        std::fs::write(dir.join("old/nested/extra.txt"), "extra").unwrap();
+        let server = MockServer::start_async().await;
+        let objects = [("out/sub/x.txt", "900150983cd24fb0d6963f7d28e17f72", 3)];
+        server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/")
+                    .query_param("prefix", "out/");
+                then.status(200).body(listing(&objects));
+            })
+            .await;
+        let download = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/out/sub/x.txt");
+                then.status(200).body("abc");
+            })
+            .await;
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        let client = client(&server);
+        let report = client
+            .sync_from_bucket("out/", &dir, &options)
+            .await
+            .unwrap();
+        assert_eq!(report.transferred, 1);
+        assert_eq!(report.deleted, 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("sub/x.txt")).unwrap(),
+            "abc"
+        );
+        assert!(!dir.join("old").exists());
+        let report = client
+            .sync_from_bucket("out/", &dir, &options)
+            .await
+            .unwrap();
+        assert_eq!(report.skipped, 1);
+        download.assert_hits_async(1).await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_bucket_error() {
+        let dir = temp_dir("sync");
+        std::fs::write(dir.join("a.txt"), "previous").unwrap();
+        let server = MockServer::start_async().await;
+        let objects = [("a.txt", "0", 3), ("b.txt", "0", 3)];
+        server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/bucket/")
+                    .query_param("list-type", "2");
+                then.status(200).body(listing(&objects));
+            })
+            .await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/a.txt");
+                then.status(500);
+            })
+            .await;
+        let download = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/bucket/b.txt");
+                then.status(200)
+                    .body("abc")
+                    .delay(std::time::Duration::from_millis(200));
+            })
+            .await;
+        let options = SyncOptions {
+            concurrency: Some(2),
+            ..Default::default()
+        };
+        let res = client(&server).sync_from_bucket("", &dir, &options).await;
+        assert!(matches!(res, Err(Error::Http(500, _))));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+            "previous"
+        );
+        assert!(!dir.join(".a.txt.download").exists());
+        download.assert_async().await;
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "abc");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_parts() {
+        let dir = temp_dir("sync");
+        let path = dir.join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let server = MockServer::start_async().await;
+        let client = client(&server);
+        let local = Local { size: 10, path };
+        let remote = |etag: &str| Remote {
+            size: 10,
+            etag: etag.to_owned(),
+        };
+        assert!(!client.unchanged(&local, &remote("0-2")).await.unwrap());
+        assert!(!client.unchanged(&local, &remote("0-x")).await.unwrap());
+        let etag = local_etag(&local.path, client.part_size, true)
+            .await
+            .unwrap();
+        assert!(client.unchanged(&local, &remote(&etag)).await.unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+use std::{env, path::PathBuf};
+
+use httpmock::MockServer;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+use super::{Client, Minio};
+
+///config of the bucket `bucket` of the mock server, with static keys
+pub(super) fn config(server: &MockServer) -> Minio {
+    Minio {
+        service: server.base_url(),
+        name: "bucket".to_owned(),
+        access_key: Some("access".to_owned()),
+        secret_key: Some("secret".to_owned()),
+        ..Default::default()
+    }
+}
+
+pub(super) fn client(server: &MockServer) -> Client {
+    Client::new(&config(server)).unwrap()
+}
+
+///new empty directory in the temporary directory
+pub(super) fn temp_dir(name: &str) -> PathBuf {
+    let suffix: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    let dir = env::temp_dir().join(format!("minio-{name}-{suffix}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,790 @@
+//! Idempotency keys for the rocket handlers.
+//!
+//! Attach an [`IdempotencyStore`] as a fairing, add the [`IdempotencyKey`]
+//! guard to the handlers to protect and wrap their response in
+//! [`Idempotent`]. The first request with a given `Idempotency-Key` header
+//! runs the handler and its response is recorded, the replays get the
+//! recorded response without running the handler and the requests sent
+//! while the first one is running are rejected with a `409 Conflict`.
+//!
+//! A key reused for a request with a different method, path or body is
+//! rejected with a `422 Unprocessable Entity`. Rocket only let the fairings
+//! peek the first 512 bytes of the body, the requests with a larger body are
+//! rejected with a `422 Unprocessable Entity` too as they can not be told
+//! apart.
+
+use std::{io::Cursor, time::Duration};
+
+use log::{debug, warn};
+use redis::Cmd;
+use rocket::{
+    data::Data,
+    fairing::{self, Fairing, Info, Kind},
+    http::{ContentType, Header, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Responder},
+    Build, Request, Response, Rocket,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::{random_token, ttl_millis, Client};
+
+///header carrying the idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+///header added to the replayed responses
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+///prefix of the record stored while the first request is running
+const PENDING: &[u8] = b"pending";
+///prefix of the record holding the response
+const DONE: &[u8] = b"done";
+///bytes of the body a fairing can peek
+const PEEK_BYTES: usize = 512;
+
+///store the response if the key still hold the reservation of the token,
+///keeping the fingerprint of the reservation
+const COMPLETE_SCRIPT: &str = r#"
+local prefix = "pending " .. ARGV[1] .. " "
+local stored = redis.call("GET", KEYS[1])
+if not stored or string.sub(stored, 1, #prefix) ~= prefix then
+    return 0
+end
+local fingerprint = string.sub(stored, #prefix + 1)
+redis.call("SET", KEYS[1], "done " .. fingerprint .. " " .. ARGV[2], "PX", ARGV[3])
+return 1
+"#;
+
+///delete the key if it still hold the reservation of the token
+const RELEASE_SCRIPT: &str = r#"
+local prefix = "pending " .. ARGV[1] .. " "
+local stored = redis.call("GET", KEYS[1])
+if not stored or string.sub(stored, 1, #prefix) ~= prefix then
+    return 0
+end
+return redis.call("DEL", KEYS[1])
+"#;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("missing the {IDEMPOTENCY_KEY_HEADER} header")]
+    MissingKey,
+    #[error("a request with the same idempotency key is in progress")]
+    InProgress,
+    #[error("the response of the idempotency key is replayed")]
+    Replayed,
+    #[error("the idempotency key was used for a different request")]
+    Mismatch,
+    #[error("the body is too large to be fingerprinted, at most {PEEK_BYTES} bytes")]
+    BodyTooLarge,
+    #[error("no idempotency store attached")]
+    NoStore,
+    #[error(transparent)]
+    Redis(#[from] super::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+///Response recorded for an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    ///encode as `<status> <content type>\n<body>`
+    fn encode(&self) -> Vec<u8> {
+        let content_type = self.content_type.as_deref().unwrap_or_default();
+        let mut res = format!("{} {}\n", self.status, content_type).into_bytes();
+        res.extend_from_slice(&self.body);
+        res
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        let end = raw.iter().position(|&b| b == b'\n')?;
+        let head = std::str::from_utf8(&raw[..end]).ok()?;
+        let (status, content_type) = head.split_once(' ')?;
+        Some(StoredResponse {
+            status: status.parse().ok()?,
+            content_type: (!content_type.is_empty()).then(|| content_type.to_owned()),
+            body: raw[end + 1..].to_vec(),
+        })
+    }
+}
+
+///Record stored under an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    Pending {
+        token: String,
+        fingerprint: String,
+    },
+    Done {
+        fingerprint: String,
+        response: StoredResponse,
+    },
+}
+
+impl Record {
+    ///encode as `pending <token> <fingerprint>` or
+    ///`done <fingerprint> <response>`
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Record::Pending { token, fingerprint } => {
+                [PENDING, format!(" {token} {fingerprint}").as_bytes()].concat()
+            }
+            Record::Done {
+                fingerprint,
+                response,
+            } => [
+                DONE,
+                format!(" {fingerprint} ").as_bytes(),
+                &response.encode(),
+            ]
+            .concat(),
+        }
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        if let Some(rest) = raw.strip_prefix(PENDING) {
+            let rest = std::str::from_utf8(rest.strip_prefix(b" ")?).ok()?;
+            let (token, fingerprint) = rest.split_once(' ')?;
+            return Some(Record::Pending {
+                token: token.to_owned(),
+                fingerprint: fingerprint.to_owned(),
+            });
+        }
+        let rest = raw.strip_prefix(DONE)?.strip_prefix(b" ")?;
+        let end = rest.iter().position(|&b| b == b' ')?;
+        Some(Record::Done {
+            fingerprint: std::str::from_utf8(&rest[..end]).ok()?.to_owned(),
+            response: StoredResponse::decode(&rest[end + 1..])?,
+        })
+    }
+
+    fn fingerprint(&self) -> &str {
+        match self {
+            Record::Pending { fingerprint, .. } | Record::Done { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+///return the hex sha256 of the method, path and body of a request
+pub fn fingerprint(method: &str, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for part in [method.as_bytes(), path.as_bytes(), body] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+///State of an idempotency key when a request try to reserve it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reservation {
+    ///first request with the key, the handler must run and the token is
+    ///needed to record its response
+    Reserved(String),
+    ///a request with the key is running
+    InProgress,
+    ///the key was already used, its response is replayed
+    Replay(StoredResponse),
+    ///the key was used by a request with a different fingerprint
+    Mismatch,
+}
+
+///Record of the idempotency keys in redis.
+///
+///The keys are stored under `<namespace>:idempotency:<method>:<path>:<key>`,
+///so a key can be reused on different endpoints. A reservation expires after
+///`lock_ttl` if the response is never recorded, the responses are kept for
+///`ttl`.
+#[derive(Debug, Clone)]
+pub struct IdempotencyStore {
+    client: Client,
+    ttl: Duration,
+    lock_ttl: Duration,
+}
+
+///key of the record before the namespace
+fn record_key(scope: &str, key: &str) -> String {
+    format!("idempotency:{scope}:{key}")
+}
+
+impl IdempotencyStore {
+    ///keep the responses for `ttl`, the reservations expire after 1 minute
+    pub fn new(client: &Client, ttl: Duration) -> Self {
+        IdempotencyStore {
+            client: client.clone(),
+            ttl,
+            lock_ttl: Duration::from_secs(60),
+        }
+    }
+
+    ///time after which a reservation without response is released
+    pub fn lock_ttl(mut self, lock_ttl: Duration) -> Self {
+        self.lock_ttl = lock_ttl;
+        self
+    }
+
+    fn key(&self, scope: &str, key: &str) -> String {
+        self.client.key(&record_key(scope, key))
+    }
+
+    ///reserve the key for the scope, usually the method and path of the
+    ///request, the fingerprint identify the request using the key
+    pub async fn reserve(&self, scope: &str, key: &str, fingerprint: &str) -> Result<Reservation> {
+        let lock_ttl = ttl_millis(self.lock_ttl, "reservation ttl")?;
+        let key = self.key(scope, key);
+        let token = random_token(16);
+        let pending = Record::Pending {
+            token: token.clone(),
+            fingerprint: fingerprint.to_owned(),
+        };
+        // the key may expire between SET and GET, try again then
+        for _ in 0..2 {
+            let mut cmd = redis::cmd("SET");
+            cmd.arg(&key)
+                .arg(pending.encode())
+                .arg("NX")
+                .arg("PX")
+                .arg(lock_ttl);
+            let res: Option<String> = self.client.query(&cmd).await?;
+            if res.is_some() {
+                return Ok(Reservation::Reserved(token));
+            }
+            let stored: Option<Vec<u8>> = self.client.query(&Cmd::get(&key)).await?;
+            let Some(value) = stored else {
+                continue;
+            };
+            let reservation = match Record::decode(&value) {
+                Some(record) if record.fingerprint() != fingerprint => Reservation::Mismatch,
+                Some(Record::Pending { .. }) => Reservation::InProgress,
+                Some(Record::Done { response, .. }) => Reservation::Replay(response),
+                None => {
+                    warn!("invalid idempotency record {key}, considered in progress");
+                    Reservation::InProgress
+                }
+            };
+            return Ok(reservation);
+        }
+        Ok(Reservation::InProgress)
+    }
+
+    ///record the response of a key reserved with the token, return false if
+    ///the reservation expired and the key was reserved again
+    pub async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        token: &str,
+        response: &StoredResponse,
+    ) -> Result<bool> {
+        let res: i64 = self
+            .client
+            .builtin_script("idempotency_complete", COMPLETE_SCRIPT)
+            .key(record_key(scope, key))
+            .arg(token)
+            .arg(response.encode())
+            .arg(ttl_millis(self.ttl, "response ttl")?)
+            .invoke()
+            .await?;
+        Ok(res == 1)
+    }
+
+    ///release a key reserved with the token so the request can be retried,
+    ///return false if the reservation expired and the key was reserved again
+    pub async fn release(&self, scope: &str, key: &str, token: &str) -> Result<bool> {
+        let res: i64 = self
+            .client
+            .builtin_script("idempotency_release", RELEASE_SCRIPT)
+            .key(record_key(scope, key))
+            .arg(token)
+            .invoke()
+            .await?;
+        Ok(res == 1)
+    }
+}
+
+///Outcome of the guard kept in the request cache for the fairing.
+enum Cached {
+    None,
+    Reserved {
+        scope: String,
+        key: String,
+        token: String,
+    },
+    Replay(StoredResponse),
+}
+
+///Fingerprint of the request computed by the fairing.
+enum Fingerprint {
+    ///the fairing is not attached
+    Missing,
+    ///the body is larger than what the fairing can peek
+    TooLarge,
+    Body(String),
+}
+
+///Set by [`Idempotent`] when the handler produced the response.
+struct Produced(bool);
+
+fn scope(request: &Request<'_>) -> String {
+    format!("{}:{}", request.method(), request.uri().path())
+}
+
+///Idempotency key of a request reserved by the guard.
+///
+///The guard fails with `400 Bad Request` if the header is missing,
+///`409 Conflict` if a request with the key is running and
+///`422 Unprocessable Entity` if the key was used for a different request or
+///the body is larger than 512 bytes. A
+///replayed request fails the guard too, the fairing then replace the error
+///with the recorded response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(key) = request.headers().get_one(IDEMPOTENCY_KEY_HEADER) else {
+            return Outcome::Error((Status::BadRequest, Error::MissingKey));
+        };
+        let Some(store) = request.rocket().state::<IdempotencyStore>() else {
+            return Outcome::Error((Status::InternalServerError, Error::NoStore));
+        };
+        let fingerprint = match request.local_cache(|| Fingerprint::Missing) {
+            Fingerprint::Body(fingerprint) => fingerprint,
+            Fingerprint::TooLarge => {
+                return Outcome::Error((Status::UnprocessableEntity, Error::BodyTooLarge))
+            }
+            Fingerprint::Missing => {
+                return Outcome::Error((Status::InternalServerError, Error::NoStore))
+            }
+        };
+        let scope = scope(request);
+        match store.reserve(&scope, key, fingerprint).await {
+            Ok(Reservation::Reserved(token)) => {
+                debug!("idempotency key {key} reserved for {scope}");
+                request.local_cache(|| Cached::Reserved {
+                    scope,
+                    key: key.to_owned(),
+                    token,
+                });
+                Outcome::Success(IdempotencyKey(key.to_owned()))
+            }
+            Ok(Reservation::InProgress) => Outcome::Error((Status::Conflict, Error::InProgress)),
+            Ok(Reservation::Mismatch) => {
+                Outcome::Error((Status::UnprocessableEntity, Error::Mismatch))
+            }
+            Ok(Reservation::Replay(response)) => {
+                debug!("idempotency key {key} replayed for {scope}");
+                request.local_cache(|| Cached::Replay(response));
+                Outcome::Error((Status::Conflict, Error::Replayed))
+            }
+            Err(e) => Outcome::Error((Status::ServiceUnavailable, e)),
+        }
+    }
+}
+
+///Response of a handler protected by an [`IdempotencyKey`], only the
+///responses wrapped in it are recorded so the errors of the other guards
+///and of the catchers are not replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Idempotent<R>(pub R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Idempotent<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let response = self.0.respond_to(request)?;
+        request.local_cache(|| Produced(true));
+        Ok(response)
+    }
+}
+
+impl IdempotencyStore {
+    ///record the response, the key is released on server errors and when
+    ///the response was not produced by the handler
+    async fn record(
+        &self,
+        scope: &str,
+        key: &str,
+        token: &str,
+        produced: bool,
+        response: &mut Response<'_>,
+    ) -> Result<bool> {
+        let status = response.status();
+        if !produced || status.class().is_server_error() {
+            return self.release(scope, key, token).await;
+        }
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("failed to read the response of {key}: {e}");
+                return self.release(scope, key, token).await;
+            }
+        };
+        let stored = StoredResponse {
+            status: status.code,
+            content_type: response.content_type().map(|c| c.to_string()),
+            body,
+        };
+        response.set_sized_body(stored.body.len(), Cursor::new(stored.body.clone()));
+        self.complete(scope, key, token, &stored).await
+    }
+}
+
+fn replay(response: &mut Response<'_>, stored: &StoredResponse) {
+    response.set_status(Status::from_code(stored.status).unwrap_or(Status::Ok));
+    response.remove_header("Content-Type");
+    if let Some(content_type) = stored
+        .content_type
+        .as_deref()
+        .and_then(ContentType::parse_flexible)
+    {
+        response.set_header(content_type);
+    }
+    response.set_header(Header::new(REPLAYED_HEADER, "true"));
+    response.set_sized_body(stored.body.len(), Cursor::new(stored.body.clone()));
+}
+
+#[rocket::async_trait]
+impl Fairing for IdempotencyStore {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency keys",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if !request.headers().contains(IDEMPOTENCY_KEY_HEADER) {
+            return;
+        }
+        let length = request
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|l| l.parse::<usize>().ok());
+        let body = data.peek(PEEK_BYTES).await;
+        // peek stops early only at the end of the body
+        if body.len() == PEEK_BYTES && length != Some(PEEK_BYTES) {
+            request.local_cache(|| Fingerprint::TooLarge);
+            return;
+        }
+        let fingerprint = fingerprint(
+            request.method().as_str(),
+            request.uri().path().as_str(),
+            body,
+        );
+        request.local_cache(|| Fingerprint::Body(fingerprint));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        match request.local_cache(|| Cached::None) {
+            Cached::None => {}
+            Cached::Replay(stored) => replay(response, stored),
+            Cached::Reserved { scope, key, token } => {
+                let Produced(produced) = request.local_cache(|| Produced(false));
+                match self.record(scope, key, token, *produced, response).await {
+                    Ok(true) => {}
+                    Ok(false) => warn!("the reservation of {key} expired before the response"),
+                    Err(e) => warn!("failed to record the response of {key}: {e}"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_idempotency {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rocket::{
+        local::asynchronous::Client as LocalClient, post, request::FromRequest, routes, State,
+    };
+
+    use super::*;
+    use crate::redis::{testing, MemoryStore, Redis};
+
+    struct Counter(AtomicUsize);
+
+    #[post("/orders", data = "<_body>")]
+    fn create(
+        _key: IdempotencyKey,
+        counter: &State<Counter>,
+        _body: String,
+    ) -> Idempotent<(Status, String)> {
+        let id = counter.0.fetch_add(1, Ordering::SeqCst) + 1;
+        Idempotent((Status::Created, id.to_string()))
+    }
+
+    #[post("/fail")]
+    fn fail(_key: IdempotencyKey) -> Idempotent<Status> {
+        Idempotent(Status::InternalServerError)
+    }
+
+    ///guard failing without an `Authorization` header
+    struct Authorized;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Authorized {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            match request.headers().contains("Authorization") {
+                true => Outcome::Success(Authorized),
+                false => Outcome::Error((Status::Unauthorized, ())),
+            }
+        }
+    }
+
+    #[post("/items")]
+    fn items(_key: IdempotencyKey, _auth: Authorized) -> Idempotent<Status> {
+        Idempotent(Status::Created)
+    }
+
+    ///client of the in-memory store, the responses can not be recorded
+    ///without lua
+    fn memory() -> Client {
+        Client::in_memory(&Redis::default(), &MemoryStore::default()).unwrap()
+    }
+
+    async fn setup(client: Client) -> (LocalClient, IdempotencyStore) {
+        let store = IdempotencyStore::new(&client, Duration::from_secs(60));
+        let rocket = rocket::build()
+            .manage(Counter(AtomicUsize::new(0)))
+            .attach(store.clone())
+            .mount("/", routes![create, fail, items]);
+        (LocalClient::untracked(rocket).await.unwrap(), store)
+    }
+
+    #[test]
+    fn test_stored_response_encoding() {
+        let stored = StoredResponse {
+            status: 201,
+            content_type: Some("application/json".to_owned()),
+            body: b"{\"a\":\n1}".to_vec(),
+        };
+        assert_eq!(StoredResponse::decode(&stored.encode()), Some(stored));
+        let stored = StoredResponse {
+            status: 204,
+            content_type: None,
+            body: vec![],
+        };
+        assert_eq!(StoredResponse::decode(&stored.encode()), Some(stored));
+        assert_eq!(StoredResponse::decode(PENDING), None);
+    }
+
+    #[test]
+    fn test_record_encoding() {
+        let pending = Record::Pending {
+            token: "abc".to_owned(),
+            fingerprint: fingerprint("POST", "/orders", b"{}"),
+        };
+        assert_eq!(Record::decode(&pending.encode()), Some(pending));
+        let done = Record::Done {
+            fingerprint: fingerprint("POST", "/orders", b""),
+            response: StoredResponse {
+                status: 201,
+                content_type: None,
+                body: b"1 2".to_vec(),
+            },
+        };
+        assert_eq!(Record::decode(&done.encode()), Some(done));
+        assert_eq!(Record::decode(PENDING), None);
+        assert_ne!(
+            fingerprint("POST", "/a", b"b"),
+            fingerprint("POST", "/ab", b"")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let Some(server) = testing::server() else {
+            return;
+        };
+        let (client, _) = setup(server).await;
+        let post = || {
+            client
+                .post("/orders")
+                .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+        };
+        let res = post().dispatch().await;
+        assert_eq!(res.status(), Status::Created);
+        assert!(res.headers().get_one(REPLAYED_HEADER).is_none());
+        assert_eq!(res.into_string().await.unwrap(), "1");
+        let res = post().dispatch().await;
+        assert_eq!(res.status(), Status::Created);
+        assert_eq!(res.headers().get_one(REPLAYED_HEADER), Some("true"));
+        assert_eq!(res.content_type(), Some(ContentType::Plain));
+        assert_eq!(res.into_string().await.unwrap(), "1");
+        let res = client
+            .post("/orders")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "b"))
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.unwrap(), "2");
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_mismatch() {
+        let Some(server) = testing::server() else {
+            return;
+        };
+        let (client, _) = setup(server).await;
+        let post = |body: &'static str| {
+            client
+                .post("/orders")
+                .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+                .body(body)
+        };
+        let res = post("first").dispatch().await;
+        assert_eq!(res.status(), Status::Created);
+        let res = post("second").dispatch().await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        let res = post("first").dispatch().await;
+        assert_eq!(res.headers().get_one(REPLAYED_HEADER), Some("true"));
+    }
+
+    #[tokio::test]
+    async fn test_guard_error_not_recorded() {
+        let Some(server) = testing::server() else {
+            return;
+        };
+        let (client, _) = setup(server).await;
+        let res = client
+            .post("/items")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Unauthorized);
+        let res = client
+            .post("/items")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+            .header(Header::new("Authorization", "token"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+        assert!(res.headers().get_one(REPLAYED_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_complete_expired_reservation() {
+        let Some(server) = testing::server() else {
+            return;
+        };
+        let (_, store) = setup(server).await;
+        let response = StoredResponse {
+            status: 201,
+            content_type: None,
+            body: b"1".to_vec(),
+        };
+        let Reservation::Reserved(first) = store.reserve("POST:/orders", "a", "f").await.unwrap()
+        else {
+            panic!("the key should be reserved");
+        };
+        let key = store.key("POST:/orders", "a");
+        store.client.query::<()>(&Cmd::del(&key)).await.unwrap();
+        let Reservation::Reserved(second) = store.reserve("POST:/orders", "a", "f").await.unwrap()
+        else {
+            panic!("the key should be reserved again");
+        };
+        assert!(!store
+            .complete("POST:/orders", "a", &first, &response)
+            .await
+            .unwrap());
+        assert!(!store.release("POST:/orders", "a", &first).await.unwrap());
+        let reserved = store.reserve("POST:/orders", "a", "f").await.unwrap();
+        assert_eq!(reserved, Reservation::InProgress);
+        assert!(store
+            .complete("POST:/orders", "a", &second, &response)
+            .await
+            .unwrap());
+        let reserved = store.reserve("POST:/orders", "a", "f").await.unwrap();
+        assert_eq!(reserved, Reservation::Replay(response));
+    }
+
+    #[tokio::test]
+    async fn test_missing_key() {
+        let (client, _) = setup(memory()).await;
+        let res = client.post("/orders").dispatch().await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        let (client, _) = setup(memory()).await;
+        let res = client
+            .post("/orders")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+            .body("a".repeat(PEEK_BYTES + 1))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        let res = client
+            .post("/orders")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "b"))
+            .header(Header::new("Content-Length", PEEK_BYTES.to_string()))
+            .body("a".repeat(PEEK_BYTES))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+    }
+
+    #[tokio::test]
+    async fn test_in_progress() {
+        let (client, store) = setup(memory()).await;
+        let reserved = store
+            .reserve("POST:/orders", "a", &fingerprint("POST", "/orders", b""))
+            .await
+            .unwrap();
+        assert!(matches!(reserved, Reservation::Reserved(_)));
+        let res = client
+            .post("/orders")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+    }
+
+    #[tokio::test]
+    async fn test_reservation_invalid_ttl() {
+        let store =
+            IdempotencyStore::new(&memory(), Duration::from_secs(60)).lock_ttl(Duration::ZERO);
+        let res = store.reserve("POST:/orders", "a", "fp").await;
+        assert!(matches!(
+            res,
+            Err(Error::Redis(crate::redis::Error::InvalidTtl(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_released() {
+        let Some(server) = testing::server() else {
+            return;
+        };
+        let (client, store) = setup(server).await;
+        let res = client
+            .post("/fail")
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "a"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::InternalServerError);
+        let reserved = store
+            .reserve("POST:/fail", "a", &fingerprint("POST", "/fail", b""))
+            .await
+            .unwrap();
+        assert!(matches!(reserved, Reservation::Reserved(_)));
+    }
+}
@@ -0,0 +1,194 @@
+use futures::{stream::BoxStream, StreamExt};
+use log::{info, warn};
+
+use super::{Client, Error, Message, Result};
+
+const KEYSPACE_CONFIG: &str = "notify-keyspace-events";
+///keyspace channel events, generic commands, strings and expirations
+const REQUIRED_FLAGS: &str = "Kg$x";
+///classes of events included in the `A` flag
+const ALL_CLASSES: &str = "g$lshzxetd";
+
+///Stream of the events of the keys matching a pattern.
+pub type KeyspaceStream = BoxStream<'static, Result<KeyEvent>>;
+
+///Operation reported by a keyspace notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Set,
+    Del,
+    ///an expiration was set on the key
+    Expire,
+    ///the key expired
+    Expired,
+    ///any other event, like `hset` or `rename_from`
+    Other(String),
+}
+
+impl From<&str> for KeyEventKind {
+    fn from(event: &str) -> Self {
+        match event {
+            "set" => KeyEventKind::Set,
+            "del" => KeyEventKind::Del,
+            "expire" => KeyEventKind::Expire,
+            "expired" => KeyEventKind::Expired,
+            other => KeyEventKind::Other(other.to_owned()),
+        }
+    }
+}
+
+///Keyspace notification of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    ///key relative to the namespace
+    pub key: String,
+    pub kind: KeyEventKind,
+}
+
+impl KeyEvent {
+    ///build the event from a message of the channel `<prefix><key>`
+    fn from_message(prefix: &str, message: Message<String>) -> Self {
+        let key = message
+            .channel
+            .strip_prefix(prefix)
+            .unwrap_or(&message.channel);
+        KeyEvent {
+            key: key.to_owned(),
+            kind: KeyEventKind::from(message.payload.as_str()),
+        }
+    }
+}
+
+///return the required flags missing from the notify-keyspace-events value
+fn missing_flags(current: &str) -> String {
+    let enabled =
+        |f: char| current.contains(f) || (current.contains('A') && ALL_CLASSES.contains(f));
+    REQUIRED_FLAGS.chars().filter(|f| !enabled(*f)).collect()
+}
+
+impl Client {
+    ///check that the keyspace notifications of the set, del and expire
+    ///events are enabled, enabling them with CONFIG SET if needed.
+    ///
+    ///Fail with [`Error::KeyspaceEvents`] if they are disabled and CONFIG
+    ///SET is not allowed, as on most managed services.
+    pub async fn enable_keyspace_events(&self) -> Result<()> {
+        let mut cmd = redis::cmd("CONFIG");
+        cmd.arg("GET").arg(KEYSPACE_CONFIG);
+        let (_, current): (String, String) = self.query_idempotent(&cmd).await?;
+        let missing = missing_flags(&current);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = redis::cmd("CONFIG");
+        cmd.arg("SET")
+            .arg(KEYSPACE_CONFIG)
+            .arg(format!("{current}{missing}"));
+        match self.query::<()>(&cmd).await {
+            Ok(()) => {
+                info!("keyspace notifications enabled with {missing}");
+                Ok(())
+            }
+            Err(e) => {
+                warn!("failed to enable the keyspace notifications: {e}");
+                Err(Error::KeyspaceEvents(missing))
+            }
+        }
+    }
+
+    ///stream the events of the keys matching the pattern, relative to the
+    ///namespace.
+    ///
+    ///The notifications are sent over pub/sub, the events happening while
+    ///the subscription is reconnecting are lost. With the cluster topology
+    ///only the events of the first node are received.
+    pub async fn keyspace_events(&self, pattern: &str) -> Result<KeyspaceStream> {
+        let channel = format!("__keyspace@{}__:", self.db);
+        let subscription = self
+            .psubscribe::<String, _>(&[format!("{channel}{}", self.key_pattern(pattern))])
+            .await?;
+        let prefix = format!("{channel}{}", self.key(""));
+        let events = subscription
+            .map(move |message| message.map(|message| KeyEvent::from_message(&prefix, message)));
+        Ok(events.boxed())
+    }
+}
+
+#[cfg(test)]
+mod test_keyspace {
+    use super::*;
+    use crate::redis::{MemoryStore, Redis};
+
+    #[test]
+    fn test_missing_flags() {
+        assert_eq!(missing_flags(""), "Kg$x");
+        assert_eq!(missing_flags("Ex"), "Kg$");
+        assert_eq!(missing_flags("KA"), "");
+        assert_eq!(missing_flags("AE"), "K");
+        assert_eq!(missing_flags("xK$g"), "");
+    }
+
+    #[test]
+    fn test_key_event_from_message() {
+        let message = Message {
+            channel: "__keyspace@0__:app:reservations:1".to_owned(),
+            pattern: Some("__keyspace@0__:app:reservations:*".to_owned()),
+            payload: "expired".to_owned(),
+        };
+        let event = KeyEvent::from_message("__keyspace@0__:app:", message);
+        let expected = KeyEvent {
+            key: "reservations:1".to_owned(),
+            kind: KeyEventKind::Expired,
+        };
+        assert_eq!(event, expected);
+        assert_eq!(
+            KeyEventKind::from("hset"),
+            KeyEventKind::Other("hset".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_events() {
+        let config = Redis {
+            namespace: Some("app".to_owned()),
+            db: Some(2),
+            ..Default::default()
+        };
+        let client = Client::in_memory(&config, &MemoryStore::default()).unwrap();
+        let mut events = client.keyspace_events("reservations:*").await.unwrap();
+        let received = client
+            .publish("__keyspace@2__:app:reservations:1", "del")
+            .await
+            .unwrap();
+        assert_eq!(received, 1);
+        client
+            .publish("__keyspace@2__:app:other", "del")
+            .await
+            .unwrap();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.key, "reservations:1");
+        assert_eq!(event.kind, KeyEventKind::Del);
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_events_namespace_escaped() {
+        let config = Redis {
+            namespace: Some("a*p".to_owned()),
+            ..Default::default()
+        };
+        let client = Client::in_memory(&config, &MemoryStore::default()).unwrap();
+        let mut events = client.keyspace_events("jobs:*").await.unwrap();
+        let received = client
+            .publish("__keyspace@0__:axp:jobs:1", "del")
+            .await
+            .unwrap();
+        assert_eq!(received, 0);
+        client
+            .publish("__keyspace@0__:a*p:jobs:2", "set")
+            .await
+            .unwrap();
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.key, "jobs:2");
+        assert_eq!(event.kind, KeyEventKind::Set);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{debug, warn};
+use redis::Cmd;
+use tokio::{runtime::Handle, task::JoinHandle, time::Instant};
+
+use super::{random_token, ttl_millis, Client, Result};
+
+///delete the key only if it still hold our token.
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+///reset the key ttl only if it still hold our token.
+const EXTEND_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+else
+    return 0
+end
+"#;
+
+const TOKEN_LENGTH: usize = 32;
+
+async fn release(client: &Client, key: &str, token: &str) -> Result<bool> {
+    let res: i64 = client
+        .builtin_script("lock_release", RELEASE_SCRIPT)
+        .key(key)
+        .arg(token)
+        .invoke()
+        .await?;
+    Ok(res == 1)
+}
+
+async fn extend(client: &Client, key: &str, token: &str, ttl: Duration) -> Result<bool> {
+    let ttl = ttl_millis(ttl, "lock ttl")?;
+    let res: i64 = client
+        .builtin_script("lock_extend", EXTEND_SCRIPT)
+        .key(key)
+        .arg(token)
+        .arg(ttl)
+        .invoke()
+        .await?;
+    Ok(res == 1)
+}
+
+///Distributed lock stored in a single redis key, prefixed with the client
+///namespace.
+///
+///The lock is acquired with `SET key token NX PX ttl`, the random token
+///ensure that only the owner can extend or release it.
+#[derive(Debug, Clone)]
+pub struct RedisLock {
+    client: Client,
+    key: String,
+    ttl: Duration,
+    retry_delay: Duration,
+    auto_renew: bool,
+}
+
+impl RedisLock {
+    ///create a new lock on the given key, the lock expire after `ttl` if
+    ///it is not released or extended, fail if `ttl` is under 1ms.
+    pub fn new<K: Into<String>>(client: &Client, key: K, ttl: Duration) -> Result<Self> {
+        ttl_millis(ttl, "lock ttl")?;
+        Ok(RedisLock {
+            client: client.clone(),
+            key: key.into(),
+            ttl,
+            retry_delay: Duration::from_millis(100),
+            auto_renew: false,
+        })
+    }
+
+    ///set the delay between two acquisition attempts in `lock_with_timeout`
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    ///extend the lock in the background while the guard is held
+    pub fn auto_renew(mut self, auto_renew: bool) -> Self {
+        self.auto_renew = auto_renew;
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    ///try to acquire the lock once, return `None` if it is already held.
+    pub async fn try_lock(&self) -> Result<Option<LockGuard>> {
+        let token = random_token(TOKEN_LENGTH);
+        let mut cmd = Cmd::new();
+        cmd.arg("SET")
+            .arg(self.client.key(&self.key))
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_millis(self.ttl, "lock ttl")?);
+        let res: Option<String> = self.client.query(&cmd).await?;
+        if res.is_none() {
+            debug!("lock {} is already held", self.key);
+            return Ok(None);
+        }
+        debug!("lock {} acquired", self.key);
+        Ok(Some(LockGuard::new(self, token)))
+    }
+
+    ///try to acquire the lock until the timeout is reached,
+    ///return `None` if the lock could not be acquired in time.
+    pub async fn lock_with_timeout(&self, timeout: Duration) -> Result<Option<LockGuard>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(guard) = self.try_lock().await? {
+                return Ok(Some(guard));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            tokio::time::sleep(self.retry_delay.min(deadline - now)).await;
+        }
+    }
+}
+
+///Guard of an acquired [`RedisLock`].
+///
+///The lock should be released with [`LockGuard::release`], if the guard is
+///dropped while still held a best effort release is spawned on the current
+///tokio runtime.
+#[derive(Debug)]
+pub struct LockGuard {
+    client: Client,
+    key: String,
+    token: String,
+    ttl: Duration,
+    held: Arc<AtomicBool>,
+    renew: Option<JoinHandle<()>>,
+    released: bool,
+}
+
+impl LockGuard {
+    fn new(lock: &RedisLock, token: String) -> Self {
+        let held = Arc::new(AtomicBool::new(true));
+        let renew = if lock.auto_renew {
+            let client = lock.client.clone();
+            let key = lock.key.clone();
+            let token = token.clone();
+            let ttl = lock.ttl;
+            let held = held.clone();
+            Some(tokio::spawn(async move {
+                let mut interval = tokio::time::interval((ttl / 3).max(Duration::from_millis(1)));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    match extend(&client, &key, &token, ttl).await {
+                        Ok(true) => debug!("lock {key} renewed"),
+                        Ok(false) => {
+                            warn!("lock {key} has been lost, stop renewing");
+                            held.store(false, Ordering::Release);
+                            return;
+                        }
+                        Err(e) => warn!("failed to renew lock {key}: {e}"),
+                    }
+                }
+            }))
+        } else {
+            None
+        };
+        LockGuard {
+            client: lock.client.clone(),
+            key: lock.key.clone(),
+            token,
+            ttl: lock.ttl,
+            held,
+            renew,
+            released: false,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    ///random token identifying this owner
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    ///return false if the auto renewal found the lock taken by someone else
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::Acquire)
+    }
+
+    ///reset the lock expiration to `ttl`, return false if the lock is no
+    ///longer owned.
+    pub async fn extend(&self, ttl: Duration) -> Result<bool> {
+        let extended = extend(&self.client, &self.key, &self.token, ttl).await?;
+        if !extended {
+            self.held.store(false, Ordering::Release);
+        }
+        Ok(extended)
+    }
+
+    ///reset the lock expiration to the lock ttl
+    pub async fn refresh(&self) -> Result<bool> {
+        self.extend(self.ttl).await
+    }
+
+    ///release the lock, return false if it was no longer owned.
+    pub async fn release(mut self) -> Result<bool> {
+        if let Some(renew) = self.renew.take() {
+            renew.abort();
+        }
+        self.released = true;
+        self.held.store(false, Ordering::Release);
+        let res = release(&self.client, &self.key, &self.token).await?;
+        debug!("lock {} released", self.key);
+        Ok(res)
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Some(renew) = self.renew.take() {
+            renew.abort();
+        }
+        if self.released {
+            return;
+        }
+        let Ok(handle) = Handle::try_current() else {
+            warn!(
+                "lock {} dropped outside of a runtime, not released",
+                self.key
+            );
+            return;
+        };
+        let client = self.client.clone();
+        let key = std::mem::take(&mut self.key);
+        let token = std::mem::take(&mut self.token);
+        handle.spawn(async move {
+            if let Err(e) = release(&client, &key, &token).await {
+                warn!("failed to release lock {key}: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_lock {
+    use super::*;
+    use crate::redis::{testing, Error};
+
+    #[test]
+    fn test_lock_invalid_ttl() {
+        let client = testing::client();
+        for ttl in [Duration::ZERO, Duration::from_micros(999)] {
+            let res = RedisLock::new(&client, "job", ttl);
+            assert!(matches!(res, Err(Error::InvalidTtl(_))));
+        }
+        assert!(RedisLock::new(&client, "job", Duration::from_millis(1)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_extend_invalid_ttl() {
+        let client = testing::client();
+        let res = extend(&client, "job", "token", Duration::ZERO).await;
+        assert!(matches!(res, Err(Error::InvalidTtl(_))));
+    }
+
+    #[tokio::test]
+    async fn test_try_lock_contention() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let lock = RedisLock::new(&client, "contention", Duration::from_secs(5)).unwrap();
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        assert_eq!(guard.token().len(), TOKEN_LENGTH);
+        assert!(lock.try_lock().await.unwrap().is_none());
+        assert!(guard.release().await.unwrap());
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        assert!(guard.release().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lock_with_timeout_expiry() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let lock = RedisLock::new(&client, "expiry", Duration::from_millis(300))
+            .unwrap()
+            .retry_delay(Duration::from_millis(20));
+        let first = lock.try_lock().await.unwrap().unwrap();
+        let res = lock
+            .lock_with_timeout(Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(res.is_none());
+        let second = lock
+            .lock_with_timeout(Duration::from_secs(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(first.token(), second.token());
+        assert!(!first.release().await.unwrap());
+        assert!(second.release().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_release_other_token() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let lock = RedisLock::new(&client, "other", Duration::from_secs(5)).unwrap();
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        assert!(!release(&client, lock.key(), "other-token").await.unwrap());
+        assert!(lock.try_lock().await.unwrap().is_none());
+        assert!(guard.release().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_extend() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let lock = RedisLock::new(&client, "extend", Duration::from_millis(200)).unwrap();
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        assert!(guard.extend(Duration::from_secs(5)).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(lock.try_lock().await.unwrap().is_none());
+        assert!(guard.is_held());
+        assert!(guard.release().await.unwrap());
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!guard.refresh().await.unwrap());
+        assert!(!guard.is_held());
+    }
+
+    #[tokio::test]
+    async fn test_auto_renew() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let lock = RedisLock::new(&client, "renew", Duration::from_millis(300))
+            .unwrap()
+            .auto_renew(true);
+        let guard = lock.try_lock().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(guard.is_held());
+        assert!(lock.try_lock().await.unwrap().is_none());
+        assert!(guard.release().await.unwrap());
+    }
+}
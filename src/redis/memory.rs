@@ -0,0 +1,774 @@
+//! In-memory stand-in of a redis server for the tests.
+//!
+//! A [`Client`] created with [`Client::in_memory`] run its commands against
+//! the store instead of a server. Strings, hashes, expirations, keys scans
+//! and pub/sub are supported, streams are not and the lua scripts fail with
+//! [`Error::Unsupported`](super::Error::Unsupported). The expirations follow
+//! the tokio clock so they can be tested with `tokio::time::pause`.
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use redis::{Arg, Cmd, ErrorKind, Msg, Pipeline, RedisError, RedisResult, Value};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+
+use super::{
+    topology::{Backend, BackendConnection},
+    Client, ManagedConnection, Redis, Target,
+};
+
+enum Data {
+    String(Vec<u8>),
+    Hash(BTreeMap<Vec<u8>, Vec<u8>>),
+}
+
+struct Entry {
+    data: Data,
+    expires_at: Option<Instant>,
+}
+
+struct Subscriber {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    tx: UnboundedSender<Msg>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: BTreeMap<Vec<u8>, Entry>,
+    subscribers: Vec<Subscriber>,
+}
+
+///Data of the in-memory backend, shared by the clones and the clients
+///created from it.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<State>>,
+}
+
+fn error(detail: &str) -> RedisError {
+    RedisError::from((
+        ErrorKind::ResponseError,
+        "An error was signalled by the server",
+        detail.to_owned(),
+    ))
+}
+
+fn wrong_type() -> RedisError {
+    error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+fn parse<T: FromStr>(arg: &[u8]) -> RedisResult<T> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| error("ERR value is not an integer or out of range"))
+}
+
+///instant after the given number of milliseconds, negative ones are
+///already expired
+fn deadline(millis: i64) -> Instant {
+    Instant::now() + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn data(value: &[u8]) -> Value {
+    Value::Data(value.to_vec())
+}
+
+fn bulk<'a, I: IntoIterator<Item = &'a Vec<u8>>>(values: I) -> Value {
+    Value::Bulk(values.into_iter().map(|v| data(v)).collect())
+}
+
+///match the string against a redis glob style pattern
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((b'[', rest)) if rest.contains(&b']') => {
+            let Some((c, s)) = s.split_first() else {
+                return false;
+            };
+            let end = rest.iter().position(|&b| b == b']').unwrap_or_default();
+            let (class, negate) = match &rest[..end] {
+                [b'^', class @ ..] => (class, true),
+                class => (class, false),
+            };
+            class_match(class, *c) != negate && glob_match(&rest[end + 1..], s)
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            s.first() == Some(escaped) && glob_match(rest, &s[1..])
+        }
+        Some((c, rest)) => s.first() == Some(c) && glob_match(rest, &s[1..]),
+    }
+}
+
+fn class_match(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+///read the `MATCH` option of the scan commands, the `COUNT` one is ignored
+///as everything is returned at once
+fn scan_pattern(options: &[Vec<u8>]) -> RedisResult<Vec<u8>> {
+    let mut pattern = b"*".to_vec();
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = value.clone(),
+            [name, _] if name.eq_ignore_ascii_case(b"COUNT") => {}
+            _ => return Err(error("ERR syntax error")),
+        }
+    }
+    Ok(pattern)
+}
+
+impl State {
+    ///remove the expired entries
+    fn purge(&mut self) {
+        let now = Instant::now();
+        self.entries
+            .retain(|_, entry| !matches!(entry.expires_at, Some(at) if at <= now));
+    }
+
+    fn string(&self, key: &[u8]) -> RedisResult<Option<&Vec<u8>>> {
+        match self.entries.get(key) {
+            Some(Entry {
+                data: Data::String(value),
+                ..
+            }) => Ok(Some(value)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    fn hash(&self, key: &[u8]) -> RedisResult<Option<&BTreeMap<Vec<u8>, Vec<u8>>>> {
+        match self.entries.get(key) {
+            Some(Entry {
+                data: Data::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type()),
+            None => Ok(None),
+        }
+    }
+
+    ///return the hash stored at the key, creating it if needed
+    fn hash_mut(&mut self, key: &[u8]) -> RedisResult<&mut BTreeMap<Vec<u8>, Vec<u8>>> {
+        let entry = self.entries.entry(key.to_vec()).or_insert_with(|| Entry {
+            data: Data::Hash(BTreeMap::new()),
+            expires_at: None,
+        });
+        match entry.data {
+            Data::Hash(ref mut hash) => Ok(hash),
+            Data::String(_) => Err(wrong_type()),
+        }
+    }
+
+    ///store a string, the expiration is reset
+    fn set_string(&mut self, key: &[u8], value: Vec<u8>, expires_at: Option<Instant>) {
+        let entry = Entry {
+            data: Data::String(value),
+            expires_at,
+        };
+        self.entries.insert(key.to_vec(), entry);
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], options: &[Vec<u8>]) -> RedisResult<Value> {
+        let (mut nx, mut xx, mut get, mut keep_ttl) = (false, false, false, false);
+        let mut expires_at = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GET" => get = true,
+                b"KEEPTTL" => keep_ttl = true,
+                unit @ (b"EX" | b"PX") => {
+                    let value: i64 =
+                        parse(options.next().ok_or_else(|| error("ERR syntax error"))?)?;
+                    if value <= 0 {
+                        return Err(error("ERR invalid expire time in 'set' command"));
+                    }
+                    let factor = if unit == b"EX" { 1000 } else { 1 };
+                    expires_at = Some(deadline(value.saturating_mul(factor)));
+                }
+                _ => return Err(error("ERR syntax error")),
+            }
+        }
+        let reply = match get {
+            true => self.string(key)?.map_or(Value::Nil, |v| data(v)),
+            false => Value::Okay,
+        };
+        let exists = self.entries.contains_key(key);
+        if (nx && exists) || (xx && !exists) {
+            return Ok(if get { reply } else { Value::Nil });
+        }
+        if keep_ttl {
+            expires_at = self.entries.get(key).and_then(|e| e.expires_at);
+        }
+        self.set_string(key, value.to_vec(), expires_at);
+        Ok(reply)
+    }
+
+    fn incr(&mut self, key: &[u8], by: i64) -> RedisResult<Value> {
+        let current: i64 = match self.string(key)? {
+            Some(value) => parse(value)?,
+            None => 0,
+        };
+        let value = current
+            .checked_add(by)
+            .ok_or_else(|| error("ERR increment or decrement would overflow"))?;
+        let expires_at = self.entries.get(key).and_then(|e| e.expires_at);
+        self.set_string(key, value.to_string().into_bytes(), expires_at);
+        Ok(Value::Int(value))
+    }
+
+    fn expire(&mut self, key: &[u8], millis: i64) -> Value {
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.expires_at = Some(deadline(millis));
+                Value::Int(1)
+            }
+            None => Value::Int(0),
+        }
+    }
+
+    fn ttl(&self, key: &[u8], millis: bool) -> Value {
+        let Some(entry) = self.entries.get(key) else {
+            return Value::Int(-2);
+        };
+        let Some(at) = entry.expires_at else {
+            return Value::Int(-1);
+        };
+        let left = at.saturating_duration_since(Instant::now()).as_millis() as i64;
+        Value::Int(if millis { left } else { (left + 500) / 1000 })
+    }
+
+    fn hset(&mut self, key: &[u8], pairs: &[Vec<u8>]) -> RedisResult<Value> {
+        if pairs.is_empty() || pairs.len() & 1 == 1 {
+            return Err(error("ERR wrong number of arguments for 'hset' command"));
+        }
+        let hash = self.hash_mut(key)?;
+        let mut added = 0;
+        for pair in pairs.chunks(2) {
+            if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                added += 1;
+            }
+        }
+        Ok(Value::Int(added))
+    }
+
+    fn hincr(&mut self, key: &[u8], field: &[u8], by: i64) -> RedisResult<Value> {
+        let hash = self.hash_mut(key)?;
+        let current: i64 = match hash.get(field) {
+            Some(value) => parse(value).map_err(|_| error("ERR hash value is not an integer"))?,
+            None => 0,
+        };
+        let value = current
+            .checked_add(by)
+            .ok_or_else(|| error("ERR increment or decrement would overflow"))?;
+        hash.insert(field.to_vec(), value.to_string().into_bytes());
+        Ok(Value::Int(value))
+    }
+
+    fn hdel(&mut self, key: &[u8], fields: &[Vec<u8>]) -> RedisResult<Value> {
+        let Some(Entry {
+            data: Data::Hash(hash),
+            ..
+        }) = self.entries.get_mut(key)
+        else {
+            return self.hash(key).map(|_| Value::Int(0));
+        };
+        let removed = fields.iter().filter(|f| hash.remove(*f).is_some()).count();
+        if hash.is_empty() {
+            self.entries.remove(key);
+        }
+        Ok(Value::Int(removed as i64))
+    }
+
+    fn keys(&self, pattern: &[u8]) -> Vec<&Vec<u8>> {
+        self.entries
+            .keys()
+            .filter(|key| glob_match(pattern, key))
+            .collect()
+    }
+
+    fn publish(&mut self, channel: &[u8], payload: &[u8]) -> Value {
+        self.subscribers.retain(|s| !s.tx.is_closed());
+        let name = String::from_utf8_lossy(channel);
+        let mut receivers = 0;
+        for subscriber in &self.subscribers {
+            for _ in subscriber.channels.iter().filter(|c| **c == name) {
+                let raw = Value::Bulk(vec![data(b"message"), data(channel), data(payload)]);
+                receivers += Msg::from_value(&raw).map_or(0, |msg| subscriber.send(msg));
+            }
+            for pattern in &subscriber.patterns {
+                if glob_match(pattern.as_bytes(), channel) {
+                    let raw = Value::Bulk(vec![
+                        data(b"pmessage"),
+                        data(pattern.as_bytes()),
+                        data(channel),
+                        data(payload),
+                    ]);
+                    receivers += Msg::from_value(&raw).map_or(0, |msg| subscriber.send(msg));
+                }
+            }
+        }
+        Value::Int(receivers)
+    }
+
+    fn execute(&mut self, args: &[Vec<u8>]) -> RedisResult<Value> {
+        self.purge();
+        let Some((name, args)) = args.split_first() else {
+            return Err(error("ERR empty command"));
+        };
+        let name = String::from_utf8_lossy(name).to_uppercase();
+        let res = match (name.as_str(), args) {
+            ("PING", []) => Value::Status("PONG".to_owned()),
+            ("PING", [message]) => data(message),
+            ("ECHO", [message]) => data(message),
+            ("WATCH", [_, ..]) | ("UNWATCH", []) => Value::Okay,
+            ("FLUSHDB" | "FLUSHALL", _) => {
+                self.entries.clear();
+                Value::Okay
+            }
+            ("DBSIZE", []) => Value::Int(self.entries.len() as i64),
+            ("GET", [key]) => self.string(key)?.map_or(Value::Nil, |v| data(v)),
+            ("SET", [key, value, options @ ..]) => self.set(key, value, options)?,
+            ("SETNX", [key, value]) => match self.entries.contains_key(key) {
+                true => Value::Int(0),
+                false => {
+                    self.set_string(key, value.clone(), None);
+                    Value::Int(1)
+                }
+            },
+            ("SETEX", [key, seconds, value]) => {
+                let seconds: i64 = parse(seconds)?;
+                let expires_at = deadline(seconds.saturating_mul(1000));
+                self.set_string(key, value.clone(), Some(expires_at));
+                Value::Okay
+            }
+            ("PSETEX", [key, millis, value]) => {
+                self.set_string(key, value.clone(), Some(deadline(parse(millis)?)));
+                Value::Okay
+            }
+            ("MGET", [_, ..]) => Value::Bulk(
+                args.iter()
+                    .map(|key| match self.entries.get(key) {
+                        Some(Entry {
+                            data: Data::String(value),
+                            ..
+                        }) => data(value),
+                        _ => Value::Nil,
+                    })
+                    .collect(),
+            ),
+            ("MSET", [_, _, ..]) if args.len() & 1 == 0 => {
+                for pair in args.chunks(2) {
+                    self.set_string(&pair[0], pair[1].clone(), None);
+                }
+                Value::Okay
+            }
+            ("INCR", [key]) => self.incr(key, 1)?,
+            ("DECR", [key]) => self.incr(key, -1)?,
+            ("INCRBY", [key, by]) => self.incr(key, parse(by)?)?,
+            ("DECRBY", [key, by]) => self.incr(key, -parse::<i64>(by)?)?,
+            ("DEL" | "UNLINK", [_, ..]) => {
+                let removed = args.iter().filter(|k| self.entries.remove(*k).is_some());
+                Value::Int(removed.count() as i64)
+            }
+            ("EXISTS", [_, ..]) => {
+                let found = args.iter().filter(|k| self.entries.contains_key(*k));
+                Value::Int(found.count() as i64)
+            }
+            ("TYPE", [key]) => Value::Status(
+                match self.entries.get(key).map(|e| &e.data) {
+                    Some(Data::String(_)) => "string",
+                    Some(Data::Hash(_)) => "hash",
+                    None => "none",
+                }
+                .to_owned(),
+            ),
+            ("KEYS", [pattern]) => bulk(self.keys(pattern)),
+            ("SCAN", [_, options @ ..]) => {
+                let keys = bulk(self.keys(&scan_pattern(options)?));
+                Value::Bulk(vec![data(b"0"), keys])
+            }
+            ("EXPIRE", [key, seconds]) => {
+                let seconds: i64 = parse(seconds)?;
+                self.expire(key, seconds.saturating_mul(1000))
+            }
+            ("PEXPIRE", [key, millis]) => self.expire(key, parse(millis)?),
+            ("PERSIST", [key]) => {
+                let expires_at = self.entries.get_mut(key).and_then(|e| e.expires_at.take());
+                Value::Int(expires_at.is_some().into())
+            }
+            ("TTL", [key]) => self.ttl(key, false),
+            ("PTTL", [key]) => self.ttl(key, true),
+            ("HSET", [key, pairs @ ..]) => self.hset(key, pairs)?,
+            ("HMSET", [key, pairs @ ..]) => {
+                self.hset(key, pairs)?;
+                Value::Okay
+            }
+            ("HSETNX", [key, field, value]) => {
+                let hash = self.hash_mut(key)?;
+                match hash.contains_key(field) {
+                    true => Value::Int(0),
+                    false => {
+                        hash.insert(field.clone(), value.clone());
+                        Value::Int(1)
+                    }
+                }
+            }
+            ("HGET", [key, field]) => {
+                let value = self.hash(key)?.and_then(|h| h.get(field));
+                value.map_or(Value::Nil, |v| data(v))
+            }
+            ("HMGET", [key, fields @ ..]) if !fields.is_empty() => {
+                let hash = self.hash(key)?;
+                let values = fields.iter().map(|f| hash.and_then(|h| h.get(f)));
+                Value::Bulk(values.map(|v| v.map_or(Value::Nil, |v| data(v))).collect())
+            }
+            ("HGETALL", [key]) => {
+                let hash = self.hash(key)?.into_iter().flatten();
+                bulk(hash.flat_map(|(field, value)| [field, value]))
+            }
+            ("HKEYS", [key]) => bulk(self.hash(key)?.into_iter().flat_map(|h| h.keys())),
+            ("HVALS", [key]) => bulk(self.hash(key)?.into_iter().flat_map(|h| h.values())),
+            ("HLEN", [key]) => Value::Int(self.hash(key)?.map_or(0, |h| h.len()) as i64),
+            ("HEXISTS", [key, field]) => {
+                let exists = self.hash(key)?.is_some_and(|h| h.contains_key(field));
+                Value::Int(exists.into())
+            }
+            ("HDEL", [key, fields @ ..]) if !fields.is_empty() => self.hdel(key, fields)?,
+            ("HINCRBY", [key, field, by]) => self.hincr(key, field, parse(by)?)?,
+            ("HSCAN", [key, _, options @ ..]) => {
+                let pattern = scan_pattern(options)?;
+                let hash = self.hash(key)?.into_iter().flatten();
+                let fields = hash.filter(|(field, _)| glob_match(&pattern, field));
+                let fields = bulk(fields.flat_map(|(field, value)| [field, value]));
+                Value::Bulk(vec![data(b"0"), fields])
+            }
+            ("PUBLISH", [channel, payload]) => self.publish(channel, payload),
+            ("EVAL" | "EVALSHA" | "SCRIPT", _) => {
+                return Err(error(
+                    "ERR lua scripts are not supported by the in-memory store",
+                ))
+            }
+            _ => {
+                return Err(error(&format!(
+                    "ERR unknown command or wrong number of arguments for '{name}'"
+                )))
+            }
+        };
+        Ok(res)
+    }
+}
+
+impl Subscriber {
+    ///send the message, return the number of receivers
+    fn send(&self, msg: Msg) -> i64 {
+        self.tx.send(msg).map_or(0, |_| 1)
+    }
+}
+
+fn cmd_args(cmd: &Cmd) -> Vec<Vec<u8>> {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(arg) => arg.to_vec(),
+            Arg::Cursor => b"0".to_vec(),
+        })
+        .collect()
+}
+
+impl MemoryStore {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    ///run a command, for the assertions of the tests
+    pub fn execute(&self, cmd: &Cmd) -> RedisResult<Value> {
+        self.state().execute(&cmd_args(cmd))
+    }
+
+    ///remove every key
+    pub fn flush(&self) {
+        self.state().entries.clear();
+    }
+}
+
+impl Backend for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn execute(&self, cmd: &Cmd) -> RedisResult<Value> {
+        MemoryStore::execute(self, cmd)
+    }
+
+    fn execute_pipeline(
+        &self,
+        pipe: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let res = {
+            let mut state = self.state();
+            pipe.cmd_iter()
+                .map(|cmd| state.execute(&cmd_args(cmd)))
+                .collect::<RedisResult<Vec<_>>>()?
+        };
+        // a transaction only read the reply of EXEC, after the queued
+        // replies
+        match offset {
+            0 => Ok(res.into_iter().take(count).collect()),
+            _ => Ok(vec![Value::Bulk(res)]),
+        }
+    }
+
+    fn subscribe(&self, channels: Vec<String>, patterns: Vec<String>) -> UnboundedReceiver<Msg> {
+        let (tx, rx) = unbounded_channel();
+        self.state().subscribers.push(Subscriber {
+            channels,
+            patterns,
+            tx,
+        });
+        rx
+    }
+}
+
+impl Client {
+    ///create a client running its commands against the in-memory store,
+    ///the namespace, timeout and retry settings of the config are used.
+    ///
+    ///The client is already connected. Transactions never conflict as the
+    ///commands are run one at a time.
+    pub fn in_memory(config: &Redis, store: &MemoryStore) -> super::Result<Self> {
+        let backend = BackendConnection::new(store.clone());
+        let mut client = Client::with_target(config, Target::Backend(backend.clone()))?;
+        client.managed = Some(ManagedConnection::Backend(backend));
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod test_memory {
+    use futures::{future::FutureExt, StreamExt};
+
+    use super::*;
+    use crate::redis::{Error, Message};
+
+    fn client() -> (Client, MemoryStore) {
+        let store = MemoryStore::default();
+        (Client::in_memory(&Redis::default(), &store).unwrap(), store)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"app:*", b"app:jobs"));
+        assert!(!glob_match(b"app:*", b"other:jobs"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+    }
+
+    #[tokio::test]
+    async fn test_strings() {
+        let (client, store) = client();
+        client.ping().await.unwrap();
+        let res: Option<String> = client.query(&Cmd::get("a")).await.unwrap();
+        assert_eq!(res, None);
+        client.query::<()>(&Cmd::set("a", "1")).await.unwrap();
+        let res: i64 = client.query(&Cmd::incr("a", 41)).await.unwrap();
+        assert_eq!(res, 42);
+        assert!(client.exists("a").await.unwrap());
+        let res: Vec<Option<String>> = client.query(&Cmd::get(&["a", "b"])).await.unwrap();
+        assert_eq!(res, vec![Some("42".to_owned()), None]);
+        let res = store.execute(&Cmd::del("a")).unwrap();
+        assert_eq!(res, Value::Int(1));
+        assert!(!client.exists("a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_options() {
+        let (client, _) = client();
+        let mut cmd = Cmd::set("a", "1");
+        cmd.arg("NX");
+        let res: Option<String> = client.query(&cmd).await.unwrap();
+        assert_eq!(res.as_deref(), Some("OK"));
+        let res: Option<String> = client.query(&cmd).await.unwrap();
+        assert_eq!(res, None);
+        let mut cmd = Cmd::set("a", "2");
+        cmd.arg("XX").arg("GET");
+        let res: Option<String> = client.query(&cmd).await.unwrap();
+        assert_eq!(res.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_hashes() {
+        let (client, _) = client();
+        client.hset("h", "f", "v").await.unwrap();
+        client.hset("h", "f", "w").await.unwrap();
+        assert!(client.hexists("h", "f").await.unwrap());
+        assert!(!client.hexists("h", "g").await.unwrap());
+        let res: HashMapReply = client.query(&Cmd::hgetall("h")).await.unwrap();
+        assert_eq!(res.get("f").map(String::as_str), Some("v"));
+        let res: i64 = client.query(&Cmd::hincr("h", "n", 3)).await.unwrap();
+        assert_eq!(res, 3);
+        let res: Result<String, Error> = client.query(&Cmd::get("h")).await;
+        assert!(matches!(res, Err(Error::Redis(e)) if e.to_string().contains("WRONGTYPE")));
+    }
+
+    type HashMapReply = std::collections::HashMap<String, String>;
+
+    #[tokio::test]
+    async fn test_ttl() {
+        tokio::time::pause();
+        let (client, _) = client();
+        client
+            .query::<()>(&Cmd::set_ex("a", "1", 10))
+            .await
+            .unwrap();
+        client.query::<()>(&Cmd::set("b", "1")).await.unwrap();
+        let res: i64 = client.query(&Cmd::ttl("a")).await.unwrap();
+        assert_eq!(res, 10);
+        let res: i64 = client.query(&Cmd::ttl("b")).await.unwrap();
+        assert_eq!(res, -1);
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert!(!client.exists("a").await.unwrap());
+        let res: i64 = client.query(&Cmd::pttl("a")).await.unwrap();
+        assert_eq!(res, -2);
+        assert!(client.exists("b").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_namespace_scan() {
+        let store = MemoryStore::default();
+        let config = Redis {
+            namespace: Some("app".to_owned()),
+            ..Default::default()
+        };
+        let client = Client::in_memory(&config, &store).unwrap();
+        client.hset("jobs:1", "f", "v").await.unwrap();
+        client.hset("jobs:2", "f", "v").await.unwrap();
+        client.hset("other", "f", "v").await.unwrap();
+        let keys: Vec<String> = client
+            .scan("jobs:*", 10)
+            .await
+            .unwrap()
+            .map(|k| k.unwrap())
+            .collect()
+            .await;
+        assert_eq!(keys, vec!["jobs:1".to_owned(), "jobs:2".to_owned()]);
+        let res = store.execute(&Cmd::exists("app:other")).unwrap();
+        assert_eq!(res, Value::Int(1));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let (client, _) = client();
+        let (a, b): (i64, i64) = client
+            .pipeline()
+            .atomic()
+            .cmd(Cmd::incr("a", 1))
+            .cmd_ignored(Cmd::expire("a", 10))
+            .cmd(Cmd::incr("a", 2))
+            .query()
+            .await
+            .unwrap();
+        assert_eq!((a, b), (1, 3));
+        let res: (i64,) = client.pipeline().cmd(Cmd::get("a")).query().await.unwrap();
+        assert_eq!(res, (3,));
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let (client, _) = client();
+        let res: (i64,) = client
+            .transaction(&["a"], 0, |connection| {
+                async move {
+                    let current: Option<i64> = Cmd::get("a").query_async(connection).await?;
+                    let mut pipe = Pipeline::new();
+                    pipe.set("a", current.unwrap_or_default() + 5).ignore();
+                    pipe.get("a");
+                    Ok(pipe)
+                }
+                .boxed()
+            })
+            .await
+            .unwrap();
+        assert_eq!(res, (5,));
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let (client, _) = client();
+        let mut jobs = client.subscribe::<String, _>(&["jobs"]).await.unwrap();
+        let mut all = client.psubscribe::<String, _>(&["jo*"]).await.unwrap();
+        assert_eq!(client.publish("jobs", "hello").await.unwrap(), 2);
+        assert_eq!(client.publish("other", "hello").await.unwrap(), 0);
+        let msg = jobs.next().await.unwrap().unwrap();
+        let expected = Message {
+            channel: "jobs".to_owned(),
+            pattern: None,
+            payload: "hello".to_owned(),
+        };
+        assert_eq!(msg, expected);
+        let msg = all.next().await.unwrap().unwrap();
+        assert_eq!(msg.pattern.as_deref(), Some("jo*"));
+        drop(jobs);
+        tokio::task::yield_now().await;
+        assert_eq!(client.publish("jobs", "again").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported() {
+        let (client, _) = client();
+        let res: Result<(), Error> = client.query(&Cmd::xlen("s")).await;
+        assert!(matches!(res, Err(Error::Redis(e)) if e.kind() == ErrorKind::ResponseError));
+        let res = client.get_simple_connection().await;
+        assert!(matches!(res, Err(Error::Unsupported(_, "memory"))));
+    }
+
+    #[tokio::test]
+    async fn test_scripts_unsupported() {
+        let (client, store) = client();
+        client.register_script("get", "return redis.call('GET', KEYS[1])");
+        let res = client.script("get").unwrap().key("a").invoke::<()>().await;
+        assert!(matches!(
+            res,
+            Err(Error::Unsupported("lua scripts", "memory"))
+        ));
+        let limiter = crate::redis::RateLimiter::new(&client, "api", 10, Duration::from_secs(1));
+        assert!(matches!(
+            limiter.check("user").await,
+            Err(Error::Unsupported(..))
+        ));
+        let res = store.execute(redis::cmd("EVALSHA").arg("abc").arg(0));
+        assert!(res.unwrap_err().to_string().contains("not supported"));
+    }
+}
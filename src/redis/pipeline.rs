@@ -0,0 +1,206 @@
+use futures::future::BoxFuture;
+use log::debug;
+use redis::{Cmd, FromRedisValue, Pipeline};
+
+use super::{topology::Target, Client, Error, PooledConnection, Result};
+
+///Batch of commands sent in a single round-trip over the managed
+///connection.
+///
+///The commands are sent as is, use [`Client::key`] to namespace their keys.
+#[derive(Clone)]
+pub struct PipelineBuilder<'a> {
+    client: &'a Client,
+    pipe: Pipeline,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    ///wrap the commands in MULTI/EXEC
+    pub fn atomic(mut self) -> Self {
+        self.pipe.atomic();
+        self
+    }
+
+    ///add a command whose reply is part of the result
+    pub fn cmd(mut self, cmd: Cmd) -> Self {
+        self.pipe.add_command(cmd);
+        self
+    }
+
+    ///add a command whose reply is discarded
+    pub fn cmd_ignored(mut self, cmd: Cmd) -> Self {
+        self.pipe.add_command(cmd).ignore();
+        self
+    }
+
+    ///number of commands in the pipeline
+    pub fn len(&self) -> usize {
+        self.pipe.cmd_iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///send the pipeline, the replies of the non ignored commands are
+    ///converted to `T`, usually a tuple.
+    pub async fn query<T: FromRedisValue>(self) -> Result<T> {
+        let mut connection = self.client.connection().await?;
+        self.client
+            .with_timeout(self.pipe.query_async(&mut connection))
+            .await
+    }
+
+    ///send the pipeline discarding the replies
+    pub async fn execute(self) -> Result<()> {
+        self.query().await
+    }
+}
+
+impl Client {
+    ///start a pipeline of commands
+    pub fn pipeline(&self) -> PipelineBuilder<'_> {
+        PipelineBuilder {
+            client: self,
+            pipe: Pipeline::new(),
+        }
+    }
+
+    ///run an optimistic transaction.
+    ///
+    ///The keys are prefixed with the namespace and watched, then `build` can
+    ///read them on the connection and return the pipeline to execute in
+    ///MULTI/EXEC. The commands of `build` are sent as is, use [`Client::key`]
+    ///to namespace their keys. If a watched key is modified before EXEC the
+    ///transaction is retried, up to `max_retries` times. It runs on a
+    ///dedicated connection as WATCH is bound to the connection, so the
+    ///cluster topology is not supported.
+    pub async fn transaction<K, T, F>(
+        &self,
+        keys: &[K],
+        max_retries: usize,
+        mut build: F,
+    ) -> Result<T>
+    where
+        K: AsRef<str>,
+        T: FromRedisValue,
+        F: for<'c> FnMut(&'c mut PooledConnection) -> BoxFuture<'c, Result<Pipeline>>,
+    {
+        if let Target::Cluster(..) = self.target {
+            return Err(Error::Unsupported("WATCH", self.target.name()));
+        }
+        let mut connection = self.get_dedicated_connection().await?;
+        for attempt in 0..=max_retries {
+            let mut watch = redis::cmd("WATCH");
+            for key in keys {
+                watch.arg(self.key(key.as_ref()));
+            }
+            self.with_timeout(watch.query_async::<_, ()>(&mut connection))
+                .await?;
+            let mut pipe = match build(&mut connection).await {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    let unwatch = redis::cmd("UNWATCH");
+                    self.with_timeout(unwatch.query_async::<_, ()>(&mut connection))
+                        .await?;
+                    return Err(e);
+                }
+            };
+            let res: Option<T> = self
+                .with_timeout(pipe.atomic().query_async(&mut connection))
+                .await?;
+            if let Some(res) = res {
+                return Ok(res);
+            }
+            debug!("transaction conflict, attempt {attempt}");
+        }
+        Err(Error::Conflict(max_retries))
+    }
+}
+
+#[cfg(test)]
+mod test_pipeline {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::redis::{
+        testing::{self, client},
+        Redis, Topology,
+    };
+
+    #[test]
+    fn test_pipeline_builder() {
+        let client = client();
+        let pipeline = client
+            .pipeline()
+            .cmd(Cmd::set("a", 1))
+            .cmd_ignored(Cmd::expire("a", 10))
+            .cmd(Cmd::get("a"));
+        assert_eq!(pipeline.len(), 3);
+        let packed = String::from_utf8(pipeline.pipe.get_packed_pipeline()).unwrap();
+        assert!(!packed.contains("MULTI"));
+    }
+
+    #[test]
+    fn test_pipeline_atomic() {
+        let client = client();
+        let pipeline = client.pipeline().atomic().cmd(Cmd::incr("a", 1));
+        let packed = String::from_utf8(pipeline.pipe.get_packed_pipeline()).unwrap();
+        assert!(packed.starts_with("*1\r\n$5\r\nMULTI"));
+        assert!(packed.contains("EXEC"));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_not_connected() {
+        let client = client();
+        let res = client.pipeline().cmd(Cmd::get("a")).execute().await;
+        assert!(matches!(res, Err(Error::Connection)));
+    }
+    #[tokio::test]
+    async fn test_transaction_cluster() {
+        let config = Redis {
+            topology: Topology::Cluster {
+                nodes: vec!["node1:6379".to_owned()],
+            },
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        let res = client
+            .transaction::<_, (), _>(&["a"], 0, |_| async { Ok(Pipeline::new()) }.boxed())
+            .await;
+        assert!(matches!(res, Err(Error::Unsupported("WATCH", "cluster"))));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_watch_namespace() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let key = client.key("a");
+        let res: (i64,) = client
+            .transaction(&["a"], 1, |connection| {
+                let (client, key, attempts) = (client.clone(), key.clone(), attempts.clone());
+                async move {
+                    // a write from another connection aborts the first EXEC
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        client.query::<()>(&Cmd::set(&key, 1)).await?;
+                    }
+                    let current: i64 = Cmd::get(&key).query_async(connection).await?;
+                    let mut pipe = Pipeline::new();
+                    pipe.set(&key, current + 1).ignore().get(&key);
+                    Ok(pipe)
+                }
+                .boxed()
+            })
+            .await
+            .unwrap();
+        assert_eq!(res, (2,));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}
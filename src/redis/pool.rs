@@ -0,0 +1,481 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
+};
+
+use log::{debug, warn};
+use redis::{
+    aio::{Connection, ConnectionLike},
+    cluster_async::ClusterConnection,
+    Cmd, Pipeline, RedisError, RedisFuture, Value,
+};
+use serde::Deserialize;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use super::{topology::BackendConnection, Client, Error, Result, Target};
+
+fn default_max_size() -> usize {
+    10
+}
+
+fn default_idle_timeout() -> u64 {
+    300
+}
+
+fn default_health_check_interval() -> u64 {
+    30
+}
+
+///Configuration of the pool of dedicated connections.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    ///maximum number of connections, checkouts wait when it is reached
+    #[serde(default = "default_max_size")]
+    pub max_size: usize,
+    ///seconds after which an unused connection is closed
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    ///seconds between two PING of the idle connections
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: default_max_size(),
+            idle_timeout: default_idle_timeout(),
+            health_check_interval: default_health_check_interval(),
+        }
+    }
+}
+
+///Connection not shared with other callers, suitable for blocking commands.
+#[non_exhaustive]
+pub enum DedicatedConnection {
+    Single(Connection),
+    Cluster(ClusterConnection),
+    Backend(BackendConnection),
+}
+
+impl ConnectionLike for DedicatedConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            DedicatedConnection::Single(c) => c.req_packed_command(cmd),
+            DedicatedConnection::Cluster(c) => c.req_packed_command(cmd),
+            DedicatedConnection::Backend(c) => c.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            DedicatedConnection::Single(c) => c.req_packed_commands(cmd, offset, count),
+            DedicatedConnection::Cluster(c) => c.req_packed_commands(cmd, offset, count),
+            DedicatedConnection::Backend(c) => c.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            DedicatedConnection::Single(c) => c.get_db(),
+            DedicatedConnection::Cluster(c) => c.get_db(),
+            DedicatedConnection::Backend(c) => c.get_db(),
+        }
+    }
+}
+
+impl Target {
+    ///open a connection used by a single caller at a time
+    pub(crate) async fn dedicated(&self) -> Result<DedicatedConnection> {
+        let connection = match self {
+            Target::Cluster(client, _) => {
+                DedicatedConnection::Cluster(client.get_async_connection().await?)
+            }
+            Target::Backend(backend) => DedicatedConnection::Backend(backend.clone()),
+            _ => DedicatedConnection::Single(self.simple_connection().await?),
+        };
+        Ok(connection)
+    }
+}
+
+struct Idle {
+    connection: DedicatedConnection,
+    since: Instant,
+}
+
+struct PoolInner {
+    target: Target,
+    config: PoolConfig,
+    idle: Mutex<VecDeque<Idle>>,
+    semaphore: Arc<Semaphore>,
+    health_check: AtomicBool,
+}
+
+impl PoolInner {
+    fn idle(&self) -> std::sync::MutexGuard<'_, VecDeque<Idle>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.idle_timeout)
+    }
+}
+
+///Pool of dedicated connections with a maximum size.
+///
+///Idle connections are closed after `idle_timeout` and checked with a PING
+///every `health_check_interval`.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+impl Pool {
+    ///create the pool, fail if it could not hold any connection
+    pub(crate) fn new(target: Target, config: PoolConfig) -> Result<Self> {
+        if config.max_size == 0 {
+            return Err(Error::InvalidPool("max_size must be positive"));
+        }
+        Ok(Pool {
+            inner: Arc::new(PoolInner {
+                target,
+                semaphore: Arc::new(Semaphore::new(config.max_size)),
+                config,
+                idle: Mutex::new(VecDeque::new()),
+                health_check: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    ///number of idle connections
+    pub fn idle(&self) -> usize {
+        self.inner.idle().len()
+    }
+
+    ///number of connections that can still be checked out
+    pub fn available(&self) -> usize {
+        self.inner.semaphore.available_permits()
+    }
+
+    ///wait for a free slot and return an idle connection or open a new one
+    pub async fn get(&self) -> Result<PooledConnection> {
+        self.spawn_health_check();
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the pool semaphore is never closed");
+        let timeout = self.inner.idle_timeout();
+        let idle = loop {
+            let Some(idle) = self.inner.idle().pop_front() else {
+                break None;
+            };
+            if idle.since.elapsed() < timeout {
+                break Some(idle.connection);
+            }
+            debug!("closing idle redis connection");
+        };
+        let connection = match idle {
+            Some(connection) => connection,
+            None => self.inner.target.dedicated().await?,
+        };
+        Ok(PooledConnection {
+            connection: Some(connection),
+            pool: Some(Arc::downgrade(&self.inner)),
+            _permit: Some(permit),
+            broken: false,
+        })
+    }
+
+    fn spawn_health_check(&self) {
+        if self.inner.health_check.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let pool = Arc::downgrade(&self.inner);
+        let period = Duration::from_secs(self.inner.config.health_check_interval.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(pool) = pool.upgrade() else {
+                    return;
+                };
+                health_check(&pool).await;
+            }
+        });
+    }
+}
+
+///close the expired idle connections and PING the others
+async fn health_check(pool: &PoolInner) {
+    let timeout = pool.idle_timeout();
+    let idle: Vec<Idle> = pool.idle().drain(..).collect();
+    let mut alive = VecDeque::with_capacity(idle.len());
+    for mut idle in idle {
+        if idle.since.elapsed() >= timeout {
+            debug!("closing idle redis connection");
+            continue;
+        }
+        match redis::cmd("PING")
+            .query_async::<_, ()>(&mut idle.connection)
+            .await
+        {
+            Ok(()) => alive.push_back(idle),
+            Err(e) => warn!("dropping unhealthy redis connection: {e}"),
+        }
+    }
+    pool.idle().extend(alive);
+}
+
+fn is_broken(err: &RedisError) -> bool {
+    err.is_io_error() || err.is_connection_dropped() || err.is_connection_refusal()
+}
+
+///Connection checked out of the [`Pool`], it is given back on drop unless
+///an I/O error was encountered or a command was cancelled, by a timeout for
+///instance, before its reply was read.
+pub struct PooledConnection {
+    connection: Option<DedicatedConnection>,
+    pool: Option<Weak<PoolInner>>,
+    _permit: Option<OwnedSemaphorePermit>,
+    broken: bool,
+}
+
+impl PooledConnection {
+    ///connection closed on drop instead of being given back to a pool
+    pub(crate) fn unpooled(connection: DedicatedConnection) -> Self {
+        PooledConnection {
+            connection: Some(connection),
+            pool: None,
+            _permit: None,
+            broken: false,
+        }
+    }
+
+    ///mark the connection broken while a command is in flight, it stays
+    ///so if the future is dropped before the reply is read
+    fn start(&mut self) -> bool {
+        std::mem::replace(&mut self.broken, true)
+    }
+
+    fn check<T>(&mut self, broken: bool, res: &redis::RedisResult<T>) {
+        self.broken = broken;
+        if let Err(e) = res {
+            self.broken |= is_broken(e);
+        }
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = DedicatedConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.connection
+            .as_ref()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.connection
+            .as_mut()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl ConnectionLike for PooledConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let broken = self.start();
+            let res = self.deref_mut().req_packed_command(cmd).await;
+            self.check(broken, &res);
+            res
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let broken = self.start();
+            let res = self
+                .deref_mut()
+                .req_packed_commands(cmd, offset, count)
+                .await;
+            self.check(broken, &res);
+            res
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.deref().get_db()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let (Some(connection), Some(pool)) = (self.connection.take(), &self.pool) else {
+            return;
+        };
+        if self.broken {
+            debug!("closing broken redis connection");
+            return;
+        }
+        if let Some(pool) = pool.upgrade() {
+            pool.idle().push_back(Idle {
+                connection,
+                since: Instant::now(),
+            });
+        }
+    }
+}
+
+impl Client {
+    ///return a connection that is not shared with other callers, taken from
+    ///the pool if one is configured, opened for this call otherwise.
+    ///
+    ///Use it for blocking commands so they do not stall the managed
+    ///connection.
+    pub async fn get_dedicated_connection(&self) -> Result<PooledConnection> {
+        match self.pool {
+            Some(ref pool) => pool.get().await,
+            None => Ok(PooledConnection::unpooled(self.target.dedicated().await?)),
+        }
+    }
+
+    pub fn pool(&self) -> Option<&Pool> {
+        self.pool.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test_pool {
+    use super::*;
+    use crate::redis::{testing, Redis};
+
+    #[test]
+    fn test_pool_config_default() {
+        use figment::{
+            providers::{Format, Yaml},
+            Figment,
+        };
+
+        let config: PoolConfig = Figment::new()
+            .merge(Yaml::string("max_size: 4"))
+            .extract()
+            .unwrap();
+        let expected = PoolConfig {
+            max_size: 4,
+            ..Default::default()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[tokio::test]
+    async fn test_pool_available() {
+        let target = Target::Standalone(redis::Client::open("redis://127.0.0.1:1").unwrap());
+        let pool = Pool::new(target, PoolConfig::default()).unwrap();
+        assert_eq!(pool.available(), 10);
+        assert_eq!(pool.idle(), 0);
+        assert!(pool.get().await.is_err());
+        assert_eq!(pool.available(), 10);
+    }
+
+    #[test]
+    fn test_pool_empty() {
+        let config = Redis {
+            pool: Some(PoolConfig {
+                max_size: 0,
+                ..Default::default()
+            }),
+            ..testing::config()
+        };
+        let res = Client::new(&config);
+        assert!(matches!(res, Err(Error::InvalidPool(_))));
+    }
+
+    ///answer BLPOP after 200ms, GET and CLIENT at once
+    async fn fake_server() -> String {
+        use tokio::{
+            io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (read, mut write) = socket.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = match line.as_str() {
+                            "BLPOP" => {
+                                tokio::time::sleep(Duration::from_millis(200)).await;
+                                b"*2\r\n$4\r\nlist\r\n$4\r\nlate\r\n"
+                            }
+                            "GET" => b"$5\r\nvalue\r\n",
+                            "CLIENT" => b"+OK\r\n",
+                            _ => continue,
+                        };
+                        if write.write_all(reply).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_pool_timeout_discard_connection() {
+        let config = crate::redis::Redis {
+            addr: fake_server().await,
+            command_timeout: Some(50),
+            pool: Some(PoolConfig {
+                max_size: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        let mut connection = client.get_dedicated_connection().await.unwrap();
+        let blpop = redis::cmd("BLPOP").arg("list").arg(0).clone();
+        let res = client
+            .with_timeout(blpop.query_async::<_, Value>(&mut connection))
+            .await;
+        assert!(matches!(res, Err(crate::redis::Error::Timeout(_))));
+        drop(connection);
+        let pool = client.pool().unwrap();
+        assert_eq!(pool.idle(), 0);
+        let mut connection = client.get_dedicated_connection().await.unwrap();
+        let value: String = client
+            .with_timeout(redis::cmd("GET").arg("key").query_async(&mut connection))
+            .await
+            .unwrap();
+        assert_eq!(value, "value");
+        drop(connection);
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use log::{debug, warn};
+use redis::{aio::PubSub, Cmd, FromRedisValue, Msg, ToRedisArgs};
+use tokio::{
+    sync::mpsc::{channel, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use super::{Client, Result, Target};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const BUFFER_SIZE: usize = 64;
+
+///A message received on a subscribed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<T> {
+    ///channel the message was published on
+    pub channel: String,
+    ///pattern that matched the channel, for `psubscribe` subscriptions
+    pub pattern: Option<String>,
+    pub payload: T,
+}
+
+impl<T: FromRedisValue> Message<T> {
+    fn from_msg(msg: &Msg) -> Result<Self> {
+        Ok(Message {
+            channel: msg.get_channel()?,
+            pattern: msg.get_pattern()?,
+            payload: msg.get_payload()?,
+        })
+    }
+}
+
+///Channels and patterns of a subscription, replayed after each reconnection.
+#[derive(Debug, Clone, Default)]
+struct Topics {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl Topics {
+    async fn open(&self, target: &Target) -> Result<PubSub> {
+        let mut pubsub = target.simple_connection().await?.into_pubsub();
+        if !self.channels.is_empty() {
+            pubsub.subscribe(&self.channels).await?;
+        }
+        if !self.patterns.is_empty() {
+            pubsub.psubscribe(&self.patterns).await?;
+        }
+        Ok(pubsub)
+    }
+}
+
+///Stream of the messages received on a subscription.
+///
+///The subscription run on its own connection, it is re-established and the
+///channels re-subscribed automatically if the connection is lost. Dropping
+///the stream close the connection.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    rx: Receiver<Result<Message<T>>>,
+    task: JoinHandle<()>,
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = Result<Message<T>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+///forward the messages of the pubsub connection, reconnect and resubscribe
+///when it is closed until the receiver is dropped.
+async fn forward<T>(
+    target: Target,
+    topics: Topics,
+    mut pubsub: PubSub,
+    tx: Sender<Result<Message<T>>>,
+) where
+    T: FromRedisValue + Send + 'static,
+{
+    loop {
+        let mut messages = pubsub.into_on_message();
+        while let Some(msg) = messages.next().await {
+            if tx.send(Message::from_msg(&msg)).await.is_err() {
+                return;
+            }
+        }
+        warn!("pubsub connection lost, resubscribing to {:?}", topics);
+        let mut delay = MIN_RECONNECT_DELAY;
+        pubsub = loop {
+            if tx.is_closed() {
+                return;
+            }
+            match topics.open(&target).await {
+                Ok(pubsub) => break pubsub,
+                Err(e) => {
+                    warn!("failed to resubscribe, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        };
+        debug!("resubscribed to {:?}", topics);
+    }
+}
+
+impl Client {
+    async fn listen<T>(&self, topics: Topics) -> Result<Subscription<T>>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        if let Target::Backend(ref backend) = self.target {
+            let mut messages = backend.subscribe(topics.channels, topics.patterns);
+            let (tx, rx) = channel(BUFFER_SIZE);
+            let task = tokio::spawn(async move {
+                while let Some(msg) = messages.recv().await {
+                    if tx.send(Message::from_msg(&msg)).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            return Ok(Subscription { rx, task });
+        }
+        let pubsub = topics.open(&self.target).await?;
+        let (tx, rx) = channel(BUFFER_SIZE);
+        let task = tokio::spawn(forward(self.target.clone(), topics, pubsub, tx));
+        Ok(Subscription { rx, task })
+    }
+
+    ///subscribe to the given channels, the payloads are converted to `T`.
+    pub async fn subscribe<T, S>(&self, channels: &[S]) -> Result<Subscription<T>>
+    where
+        T: FromRedisValue + Send + 'static,
+        S: AsRef<str>,
+    {
+        let topics = Topics {
+            channels: channels.iter().map(|c| c.as_ref().to_owned()).collect(),
+            ..Default::default()
+        };
+        self.listen(topics).await
+    }
+
+    ///subscribe to the channels matching the given patterns, the payloads
+    ///are converted to `T`.
+    pub async fn psubscribe<T, S>(&self, patterns: &[S]) -> Result<Subscription<T>>
+    where
+        T: FromRedisValue + Send + 'static,
+        S: AsRef<str>,
+    {
+        let topics = Topics {
+            patterns: patterns.iter().map(|p| p.as_ref().to_owned()).collect(),
+            ..Default::default()
+        };
+        self.listen(topics).await
+    }
+
+    ///publish redis command, return the number of clients that received
+    ///the message.
+    pub async fn publish<M>(&self, channel: &str, message: M) -> Result<usize>
+    where
+        M: ToRedisArgs + Send + Sync,
+    {
+        self.query(&Cmd::publish(channel, message)).await
+    }
+}
+
+#[cfg(test)]
+mod test_pubsub {
+    use redis::Value;
+
+    use super::*;
+    use crate::redis::testing::data;
+
+    #[test]
+    fn test_message_from_msg() {
+        let raw = Value::Bulk(vec![data("message"), data("jobs"), data("42")]);
+        let msg = Msg::from_value(&raw).unwrap();
+        let res: Message<u32> = Message::from_msg(&msg).unwrap();
+        let expected = Message {
+            channel: "jobs".to_owned(),
+            pattern: None,
+            payload: 42,
+        };
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn test_message_from_pattern_msg() {
+        let raw = Value::Bulk(vec![
+            data("pmessage"),
+            data("jobs.*"),
+            data("jobs.new"),
+            data("hello"),
+        ]);
+        let msg = Msg::from_value(&raw).unwrap();
+        let res: Message<String> = Message::from_msg(&msg).unwrap();
+        assert_eq!(res.pattern.as_deref(), Some("jobs.*"));
+        assert_eq!(res.channel, "jobs.new");
+        assert_eq!(res.payload, "hello");
+    }
+
+    #[test]
+    fn test_message_invalid_payload() {
+        let raw = Value::Bulk(vec![data("message"), data("jobs"), data("nan")]);
+        let msg = Msg::from_value(&raw).unwrap();
+        assert!(Message::<u32>::from_msg(&msg).is_err());
+    }
+}
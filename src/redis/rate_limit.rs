@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use log::debug;
+
+use super::{as_millis, random_token, Client, Error, Result};
+
+///Count the hits in a window starting at the first hit.
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local current = redis.call("INCR", KEYS[1])
+if current == 1 then
+    redis.call("PEXPIRE", KEYS[1], window)
+end
+local ttl = redis.call("PTTL", KEYS[1])
+if ttl < 0 then
+    redis.call("PEXPIRE", KEYS[1], window)
+    ttl = window
+end
+if current <= limit then
+    return {1, limit - current, 0}
+end
+return {0, 0, ttl}
+"#;
+
+///Keep a log of the hits timestamps in a sorted set.
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local time = redis.call("TIME")
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+redis.call("ZREMRANGEBYSCORE", KEYS[1], "-inf", now - window)
+local count = redis.call("ZCARD", KEYS[1])
+if count < limit then
+    redis.call("ZADD", KEYS[1], now, now .. "-" .. ARGV[3])
+    redis.call("PEXPIRE", KEYS[1], window)
+    return {1, limit - count - 1, 0}
+end
+local oldest = redis.call("ZRANGE", KEYS[1], 0, 0, "WITHSCORES")
+return {0, 0, tonumber(oldest[2]) + window - now}
+"#;
+
+///Refill `limit` tokens per window, each hit consume one token.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local rate = capacity / window
+local time = redis.call("TIME")
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local state = redis.call("HMGET", KEYS[1], "tokens", "ts")
+local tokens = tonumber(state[1]) or capacity
+local ts = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local allowed = 0
+local retry = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+else
+    retry = math.ceil((1 - tokens) / rate)
+end
+redis.call("HSET", KEYS[1], "tokens", tokens, "ts", now)
+redis.call("PEXPIRE", KEYS[1], window)
+return {allowed, math.floor(tokens), retry}
+"#;
+
+///Algorithm used to count the hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    ///at most `limit` hits per window, the window start at the first hit
+    #[default]
+    FixedWindow,
+    ///at most `limit` hits in any window ending now
+    SlidingWindow,
+    ///bucket of `limit` tokens refilled continuously over the window
+    TokenBucket,
+}
+
+impl Algorithm {
+    fn script(&self) -> (&'static str, &'static str) {
+        match self {
+            Algorithm::FixedWindow => ("rate_limit_fixed_window", FIXED_WINDOW_SCRIPT),
+            Algorithm::SlidingWindow => ("rate_limit_sliding_window", SLIDING_WINDOW_SCRIPT),
+            Algorithm::TokenBucket => ("rate_limit_token_bucket", TOKEN_BUCKET_SCRIPT),
+        }
+    }
+}
+
+///Result of a rate limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub allowed: bool,
+    pub limit: u64,
+    ///hits left in the current window
+    pub remaining: u64,
+    ///time to wait before the next hit is allowed, set if the hit was
+    ///rejected
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    fn from_reply(limit: u64, (allowed, remaining, retry_after): (i64, i64, i64)) -> Self {
+        let allowed = allowed == 1;
+        RateLimit {
+            allowed,
+            limit,
+            remaining: remaining.max(0) as u64,
+            retry_after: (!allowed).then(|| Duration::from_millis(retry_after.max(0) as u64)),
+        }
+    }
+}
+
+///Rate limiter shared across the replicas through redis.
+///
+///The counters are stored under `<namespace>:<name>:<identifier>`, the check and the
+///update are done atomically by a lua script.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    client: Client,
+    name: String,
+    algorithm: Algorithm,
+    limit: u64,
+    window: Duration,
+}
+
+impl RateLimiter {
+    ///create a rate limiter allowing `limit` hits per `window`
+    pub fn new<N: Into<String>>(client: &Client, name: N, limit: u64, window: Duration) -> Self {
+        RateLimiter {
+            client: client.clone(),
+            name: name.into(),
+            algorithm: Algorithm::default(),
+            limit,
+            window,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    ///window in milliseconds, fail if the limit or the window is zero
+    fn window_millis(&self) -> Result<u64> {
+        if self.limit == 0 {
+            return Err(Error::InvalidRateLimit("the limit must be positive"));
+        }
+        match as_millis(self.window) {
+            0 => Err(Error::InvalidRateLimit("the window must be at least 1ms")),
+            window => Ok(window),
+        }
+    }
+
+    fn key(&self, identifier: &str) -> String {
+        format!("{}:{}", self.name, identifier)
+    }
+
+    ///record a hit for the identifier (user id, ip, ...) and return
+    ///whether it is allowed.
+    pub async fn check(&self, identifier: &str) -> Result<RateLimit> {
+        let window = self.window_millis()?;
+        let nonce = random_token(8);
+        let (name, source) = self.algorithm.script();
+        let reply: (i64, i64, i64) = self
+            .client
+            .builtin_script(name, source)
+            .key(self.key(identifier))
+            .arg(self.limit)
+            .arg(window)
+            .arg(nonce)
+            .invoke()
+            .await?;
+        let res = RateLimit::from_reply(self.limit, reply);
+        debug!("rate limit {} for {identifier}: {:?}", self.name, res);
+        Ok(res)
+    }
+
+    ///reset the counter of the identifier
+    pub async fn reset(&self, identifier: &str) -> Result<()> {
+        let cmd = redis::Cmd::del(self.client.key(&self.key(identifier)));
+        self.client.query_idempotent(&cmd).await
+    }
+}
+
+#[cfg(test)]
+mod test_rate_limit {
+    use super::*;
+    use crate::redis::testing;
+
+    #[test]
+    fn test_rate_limit_allowed() {
+        let res = RateLimit::from_reply(10, (1, 7, 0));
+        let expected = RateLimit {
+            allowed: true,
+            limit: 10,
+            remaining: 7,
+            retry_after: None,
+        };
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn test_rate_limit_rejected() {
+        let res = RateLimit::from_reply(10, (0, 0, 1500));
+        assert!(!res.allowed);
+        assert_eq!(res.remaining, 0);
+        assert_eq!(res.retry_after, Some(Duration::from_millis(1500)));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_invalid() {
+        let client = testing::client();
+        let limiter = RateLimiter::new(&client, "api", 0, Duration::from_secs(1));
+        let res = limiter.check("user").await;
+        assert!(matches!(res, Err(Error::InvalidRateLimit(_))));
+        let limiter = RateLimiter::new(&client, "api", 10, Duration::from_micros(500));
+        let res = limiter.check("user").await;
+        assert!(matches!(res, Err(Error::InvalidRateLimit(_))));
+        let limiter = RateLimiter::new(&client, "api", 10, Duration::from_secs(1));
+        assert_eq!(limiter.window_millis().unwrap(), 1000);
+    }
+
+    #[test]
+    fn test_rate_limit_negative_reply() {
+        let res = RateLimit::from_reply(1, (0, -3, -1));
+        assert_eq!(res.remaining, 0);
+        assert_eq!(res.retry_after, Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_fixed_window() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let limiter = RateLimiter::new(&client, "fixed", 2, Duration::from_millis(300));
+        assert_eq!(limiter.check("user").await.unwrap().remaining, 1);
+        assert_eq!(limiter.check("user").await.unwrap().remaining, 0);
+        let res = limiter.check("user").await.unwrap();
+        assert!(!res.allowed);
+        let retry_after = res.retry_after.unwrap();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_millis(300));
+        assert!(limiter.check("other").await.unwrap().allowed);
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let res = limiter.check("user").await.unwrap();
+        assert!(res.allowed);
+        assert_eq!(res.remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let limiter = RateLimiter::new(&client, "sliding", 2, Duration::from_millis(400))
+            .algorithm(Algorithm::SlidingWindow);
+        assert!(limiter.check("user").await.unwrap().allowed);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(limiter.check("user").await.unwrap().remaining, 0);
+        let res = limiter.check("user").await.unwrap();
+        assert!(!res.allowed);
+        assert!(res.retry_after.unwrap() <= Duration::from_millis(200));
+        // only the first hit left the window
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let res = limiter.check("user").await.unwrap();
+        assert!(res.allowed);
+        assert_eq!(res.remaining, 0);
+        assert!(!limiter.check("user").await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let limiter = RateLimiter::new(&client, "bucket", 2, Duration::from_millis(400))
+            .algorithm(Algorithm::TokenBucket);
+        assert!(limiter.check("user").await.unwrap().allowed);
+        assert!(limiter.check("user").await.unwrap().allowed);
+        let res = limiter.check("user").await.unwrap();
+        assert!(!res.allowed);
+        assert!(res.retry_after.unwrap() <= Duration::from_millis(200));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(limiter.check("user").await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        let limiter = RateLimiter::new(&client, "reset", 1, Duration::from_secs(60));
+        assert!(limiter.check("user").await.unwrap().allowed);
+        assert!(!limiter.check("user").await.unwrap().allowed);
+        limiter.reset("user").await.unwrap();
+        assert!(limiter.check("user").await.unwrap().allowed);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{future::Future, time::Duration};
+
+use log::warn;
+use redis::{Cmd, ErrorKind, FromRedisValue, RedisResult};
+use serde::Deserialize;
+
+use super::{Client, Error, Result};
+
+fn default_max_retries() -> usize {
+    3
+}
+
+fn default_min_delay() -> u64 {
+    50
+}
+
+fn default_max_delay() -> u64 {
+    2000
+}
+
+///Retry policy of the idempotent commands.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct RetryConfig {
+    ///number of retries after the first attempt
+    #[serde(default = "default_max_retries")]
+    pub max_retries: usize,
+    ///milliseconds to wait before the first retry, doubled on each retry
+    #[serde(default = "default_min_delay")]
+    pub min_delay: u64,
+    ///maximum milliseconds to wait between two retries
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: default_max_retries(),
+            min_delay: default_min_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+impl RetryConfig {
+    ///delay before the retry following the given attempt, starting at 0
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 1u64.checked_shl(attempt as u32).unwrap_or(u64::MAX);
+        Duration::from_millis(self.min_delay.saturating_mul(factor).min(self.max_delay))
+    }
+}
+
+impl Error {
+    ///return true if the command may succeed when retried
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Redis(e) => {
+                e.is_io_error()
+                    || e.is_connection_dropped()
+                    || e.is_connection_refusal()
+                    || e.is_timeout()
+                    || matches!(
+                        e.kind(),
+                        ErrorKind::TryAgain
+                            | ErrorKind::BusyLoadingError
+                            | ErrorKind::ClusterDown
+                            | ErrorKind::MasterDown
+                    )
+            }
+            _ => false,
+        }
+    }
+}
+
+///await the command, failing with [`Error::Timeout`] after `timeout`
+pub(crate) async fn timeout<T, F>(timeout: Option<Duration>, fut: F) -> Result<T>
+where
+    F: Future<Output = RedisResult<T>>,
+{
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(Error::Timeout(timeout)),
+        },
+        None => Ok(fut.await?),
+    }
+}
+
+impl Client {
+    ///await the command with the client command timeout
+    pub(crate) async fn with_timeout<T, F>(&self, fut: F) -> Result<T>
+    where
+        F: Future<Output = RedisResult<T>>,
+    {
+        timeout(self.timeout, fut).await
+    }
+
+    ///await a command blocking on the server for `block`, the command
+    ///timeout start after it
+    pub(crate) async fn with_block_timeout<T, F>(&self, block: Duration, fut: F) -> Result<T>
+    where
+        F: Future<Output = RedisResult<T>>,
+    {
+        timeout(self.timeout.map(|t| t + block), fut).await
+    }
+
+    ///send the command on the managed connection with the command timeout
+    pub async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T> {
+        let mut connection = self.connection().await?;
+        self.with_timeout(cmd.query_async(&mut connection)).await
+    }
+
+    ///send the command on the managed connection with the command timeout,
+    ///transient failures are retried according to the retry policy so the
+    ///command must be idempotent.
+    pub async fn query_idempotent<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match self.query(cmd).await {
+                Err(e) if e.is_transient() => {
+                    let Some(ref retry) = self.retry else {
+                        return Err(e);
+                    };
+                    if attempt >= retry.max_retries {
+                        return Err(e);
+                    }
+                    let delay = retry.backoff(attempt);
+                    warn!("redis command failed: {e}, retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_retry {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let retry = RetryConfig::default();
+        assert_eq!(retry.backoff(0), Duration::from_millis(50));
+        assert_eq!(retry.backoff(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff(3), Duration::from_millis(400));
+        assert_eq!(retry.backoff(10), Duration::from_millis(2000));
+        assert_eq!(retry.backoff(100), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let io = redis::RedisError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(Error::Redis(io).is_transient());
+        assert!(Error::Timeout(Duration::from_secs(1)).is_transient());
+        let busy = redis::RedisError::from((ErrorKind::BusyLoadingError, "loading"));
+        assert!(Error::Redis(busy).is_transient());
+        let wrong = redis::RedisError::from((ErrorKind::TypeError, "wrong type"));
+        assert!(!Error::Redis(wrong).is_transient());
+        assert!(!Error::Connection.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let res = timeout(Some(Duration::from_millis(10)), async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        })
+        .await;
+        assert!(matches!(res, Err(Error::Timeout(_))));
+        let res = timeout(None, async { Ok(1) }).await;
+        assert_eq!(res.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_not_connected() {
+        let config = crate::redis::Redis {
+            command_timeout: Some(100),
+            retry: Some(RetryConfig::default()),
+            ..crate::redis::testing::config()
+        };
+        let client = Client::new(&config).unwrap();
+        assert_eq!(client.timeout, Some(Duration::from_millis(100)));
+        let res = client.query_idempotent::<()>(&redis::cmd("PING")).await;
+        assert!(matches!(res, Err(Error::Connection)));
+    }
+}
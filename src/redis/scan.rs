@@ -0,0 +1,229 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use redis::{Cmd, FromRedisValue};
+
+use super::{retry::timeout, Client, Error, ManagedConnection, Result, Target};
+
+///Stream of the items returned by a cursor based scan.
+pub type ScanStream<T> = BoxStream<'static, Result<T>>;
+
+struct ScanState<T> {
+    connection: ManagedConnection,
+    timeout: Option<Duration>,
+    cursor: u64,
+    buffer: VecDeque<T>,
+    done: bool,
+}
+
+///build the `<command> [key] cursor MATCH pattern COUNT count` command
+fn scan_cmd(command: &str, key: Option<&str>, cursor: u64, pattern: &str, count: usize) -> Cmd {
+    let mut cmd = redis::cmd(command);
+    if let Some(key) = key {
+        cmd.arg(key);
+    }
+    cmd.arg(cursor)
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(count);
+    cmd
+}
+
+///escape the glob special characters so the text is matched literally
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+///drive the cursor until the server return 0, yielding the items page by
+///page.
+fn scan_stream<T>(
+    connection: ManagedConnection,
+    timeout_after: Option<Duration>,
+    command: &'static str,
+    key: Option<String>,
+    pattern: String,
+    count: usize,
+) -> ScanStream<T>
+where
+    T: FromRedisValue + Send + 'static,
+{
+    let state = ScanState {
+        connection,
+        timeout: timeout_after,
+        cursor: 0,
+        buffer: VecDeque::new(),
+        done: false,
+    };
+    stream::try_unfold(state, move |mut state| {
+        let key = key.clone();
+        let pattern = pattern.clone();
+        async move {
+            loop {
+                if let Some(item) = state.buffer.pop_front() {
+                    return Ok(Some((item, state)));
+                }
+                if state.done {
+                    return Ok(None);
+                }
+                let cmd = scan_cmd(command, key.as_deref(), state.cursor, &pattern, count);
+                let (cursor, items): (u64, Vec<T>) =
+                    timeout(state.timeout, cmd.query_async(&mut state.connection)).await?;
+                state.cursor = cursor;
+                state.done = cursor == 0;
+                state.buffer.extend(items);
+            }
+        }
+    })
+    .boxed()
+}
+
+impl Client {
+    ///return the glob pattern prefixed with the namespace, the namespace is
+    ///escaped so it is matched literally
+    pub(crate) fn key_pattern(&self, pattern: &str) -> String {
+        match self.namespace {
+            Some(ref namespace) => format!("{}:{pattern}", escape_glob(namespace)),
+            None => pattern.to_owned(),
+        }
+    }
+
+    ///iterate over the keys matching the pattern with SCAN, `count` is a
+    ///hint of the number of keys returned per call.
+    ///
+    ///The pattern and the returned keys are relative to the namespace. The
+    ///cursor is bound to a single node so the cluster topology is not
+    ///supported.
+    pub async fn scan(&self, pattern: &str, count: usize) -> Result<ScanStream<String>> {
+        if let Target::Cluster(..) = self.target {
+            return Err(Error::Unsupported("SCAN", self.target.name()));
+        }
+        let connection = self.connection().await?;
+        let prefix_len = self.key("").len();
+        let pattern = self.key_pattern(pattern);
+        let keys = scan_stream::<String>(connection, self.timeout, "SCAN", None, pattern, count);
+        Ok(keys
+            .map(move |key| key.map(|mut key| key.split_off(prefix_len)))
+            .boxed())
+    }
+
+    ///iterate over the fields and values of a hash matching the pattern
+    ///with HSCAN.
+    pub async fn hscan<V>(
+        &self,
+        key: &str,
+        pattern: &str,
+        count: usize,
+    ) -> Result<ScanStream<(String, V)>>
+    where
+        V: FromRedisValue + Send + 'static,
+    {
+        let connection = self.connection().await?;
+        Ok(scan_stream(
+            connection,
+            self.timeout,
+            "HSCAN",
+            Some(self.key(key)),
+            pattern.to_owned(),
+            count,
+        ))
+    }
+
+    ///iterate over the members of a set matching the pattern with SSCAN.
+    pub async fn sscan<T>(&self, key: &str, pattern: &str, count: usize) -> Result<ScanStream<T>>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        let connection = self.connection().await?;
+        Ok(scan_stream(
+            connection,
+            self.timeout,
+            "SSCAN",
+            Some(self.key(key)),
+            pattern.to_owned(),
+            count,
+        ))
+    }
+
+    ///iterate over the members and scores of a sorted set matching the
+    ///pattern with ZSCAN.
+    pub async fn zscan<T>(
+        &self,
+        key: &str,
+        pattern: &str,
+        count: usize,
+    ) -> Result<ScanStream<(T, f64)>>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        let connection = self.connection().await?;
+        Ok(scan_stream(
+            connection,
+            self.timeout,
+            "ZSCAN",
+            Some(self.key(key)),
+            pattern.to_owned(),
+            count,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test_scan {
+    use super::*;
+    use crate::redis::{Redis, Topology};
+
+    fn packed(cmd: &Cmd) -> String {
+        String::from_utf8(cmd.get_packed_command()).unwrap()
+    }
+
+    #[test]
+    fn test_scan_cmd() {
+        let cmd = scan_cmd("SCAN", None, 12, "app:*", 100);
+        assert_eq!(
+            packed(&cmd),
+            "*6\r\n$4\r\nSCAN\r\n$2\r\n12\r\n$5\r\nMATCH\r\n$5\r\napp:*\r\n$5\r\nCOUNT\r\n$3\r\n100\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hscan_cmd() {
+        let cmd = scan_cmd("HSCAN", Some("app:h"), 0, "*", 10);
+        assert!(packed(&cmd).starts_with("*7\r\n$5\r\nHSCAN\r\n$5\r\napp:h\r\n$1\r\n0\r\n"));
+    }
+
+    #[test]
+    fn test_escape_glob() {
+        assert_eq!(escape_glob("app"), "app");
+        assert_eq!(escape_glob(r"a*b?[c]\\"), r"a\*b\?\[c\]\\\\");
+    }
+
+    #[tokio::test]
+    async fn test_scan_cluster() {
+        let config = Redis {
+            topology: Topology::Cluster {
+                nodes: vec!["node1:6379".to_owned()],
+            },
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        let res = client.scan("*", 10).await;
+        assert!(matches!(res, Err(Error::Unsupported("SCAN", "cluster"))));
+    }
+
+    #[tokio::test]
+    async fn test_scan_not_connected() {
+        let client = crate::redis::testing::client();
+        assert!(matches!(client.scan("*", 10).await, Err(Error::Connection)));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use log::debug;
+use redis::{FromRedisValue, Script, ToRedisArgs};
+
+use super::{Client, Error, Result};
+
+///Lua scripts registered by name, shared by the clones of a client.
+#[derive(Clone, Default)]
+pub struct ScriptRegistry {
+    scripts: Arc<RwLock<HashMap<String, Arc<Script>>>>,
+}
+
+impl ScriptRegistry {
+    ///register the script under the name, replacing any previous one, and
+    ///return its sha1.
+    pub fn register(&self, name: &str, source: &str) -> String {
+        let script = Script::new(source);
+        let hash = script.get_hash().to_owned();
+        self.scripts
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_owned(), Arc::new(script));
+        hash
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Script>> {
+        self.scripts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    ///return the script registered under the name, registering the source
+    ///if there is none.
+    pub(crate) fn get_or_register(&self, name: &str, source: &str) -> Arc<Script> {
+        if let Some(script) = self.get(name) {
+            return script;
+        }
+        self.scripts
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(name.to_owned())
+            .or_insert_with(|| Arc::new(Script::new(source)))
+            .clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.scripts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for ScriptRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptRegistry")
+            .field("scripts", &self.names())
+            .finish()
+    }
+}
+
+///Invocation of a registered script.
+///
+///The script is called by sha1 with EVALSHA, it is loaded again if the
+///server answer NOSCRIPT, after a restart or a failover.
+pub struct ScriptCall<'a> {
+    client: &'a Client,
+    script: Arc<Script>,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+}
+
+impl<'a> ScriptCall<'a> {
+    pub(crate) fn new(client: &'a Client, script: Arc<Script>) -> Self {
+        ScriptCall {
+            client,
+            script,
+            keys: Vec::new(),
+            args: Vec::new(),
+        }
+    }
+
+    ///add a key, prefixed with the client namespace
+    pub fn key<K: ToRedisArgs>(mut self, key: K) -> Self {
+        let keys = key.to_redis_args().into_iter();
+        self.keys.extend(keys.map(|k| self.client.key_bytes(k)));
+        self
+    }
+
+    pub fn arg<A: ToRedisArgs>(mut self, arg: A) -> Self {
+        self.args.extend(arg.to_redis_args());
+        self
+    }
+
+    ///run the script and convert its reply to `T`
+    pub async fn invoke<T: FromRedisValue>(self) -> Result<T> {
+        self.client.check_scripts()?;
+        let mut connection = self.client.connection().await?;
+        let mut invocation = self.script.prepare_invoke();
+        for key in &self.keys {
+            invocation.key(key);
+        }
+        for arg in &self.args {
+            invocation.arg(arg);
+        }
+        self.client
+            .with_timeout(invocation.invoke_async(&mut connection))
+            .await
+    }
+}
+
+impl Client {
+    fn check_scripts(&self) -> Result<()> {
+        match self.target.supports_scripts() {
+            true => Ok(()),
+            false => Err(Error::Unsupported("lua scripts", self.target.name())),
+        }
+    }
+
+    ///register a lua script under the name and return its sha1
+    pub fn register_script(&self, name: &str, source: &str) -> String {
+        self.scripts.register(name, source)
+    }
+
+    pub fn scripts(&self) -> &ScriptRegistry {
+        &self.scripts
+    }
+
+    ///prepare the invocation of a registered script
+    pub fn script(&self, name: &str) -> Result<ScriptCall<'_>> {
+        let script = self
+            .scripts
+            .get(name)
+            .ok_or_else(|| Error::UnknownScript(name.to_owned()))?;
+        Ok(ScriptCall::new(self, script))
+    }
+
+    ///prepare the invocation of a script used internally, registering it
+    ///on first use in a registry the user scripts cannot replace
+    pub(crate) fn builtin_script(&self, name: &str, source: &str) -> ScriptCall<'_> {
+        ScriptCall::new(self, self.builtins.get_or_register(name, source))
+    }
+
+    ///load every registered and builtin script in the server cache with
+    ///SCRIPT LOAD
+    pub async fn load_scripts(&self) -> Result<()> {
+        self.check_scripts()?;
+        let mut connection = self.connection().await?;
+        let scripts: Vec<(String, Arc<Script>)> = [&self.scripts, &self.builtins]
+            .into_iter()
+            .flat_map(|registry| {
+                registry
+                    .scripts
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .map(|(name, script)| (name.clone(), script.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        for (name, script) in scripts {
+            let invocation = script.prepare_invoke();
+            let hash: String = self
+                .with_timeout(invocation.load_async(&mut connection))
+                .await?;
+            debug!("script {name} loaded: {hash}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_script {
+    use super::*;
+    use crate::redis::{
+        testing::{self, client},
+        Redis,
+    };
+
+    const SCRIPT: &str = "return redis.call('GET', KEYS[1])";
+
+    #[test]
+    fn test_register_script() {
+        let client = client();
+        let hash = client.register_script("get", SCRIPT);
+        assert_eq!(hash, Script::new(SCRIPT).get_hash());
+        assert_eq!(client.clone().scripts().names(), vec!["get".to_owned()]);
+    }
+
+    #[test]
+    fn test_unknown_script() {
+        let client = client();
+        let res = client.script("missing");
+        assert!(matches!(res, Err(Error::UnknownScript(name)) if name == "missing"));
+    }
+
+    #[test]
+    fn test_builtin_script_not_replaced() {
+        let client = client();
+        let builtin = client
+            .builtin_script("get", SCRIPT)
+            .script
+            .get_hash()
+            .to_owned();
+        let hash = client.register_script("get", "return 1");
+        assert_ne!(hash, builtin);
+        let clone = client.clone();
+        let call = clone.builtin_script("get", SCRIPT);
+        assert_eq!(call.script.get_hash(), builtin);
+        assert_eq!(client.script("get").unwrap().script.get_hash(), hash);
+    }
+
+    #[test]
+    fn test_builtin_script_not_listed() {
+        let client = client();
+        client.builtin_script("lock_release", SCRIPT);
+        assert!(client.scripts().names().is_empty());
+        assert!(matches!(
+            client.script("lock_release"),
+            Err(Error::UnknownScript(_))
+        ));
+    }
+
+    #[test]
+    fn test_script_call_args() {
+        let client = client();
+        client.register_script("get", SCRIPT);
+        let call = client.script("get").unwrap().key("a").arg(&[1, 2]).arg("b");
+        assert_eq!(call.keys, vec![b"a".to_vec()]);
+        assert_eq!(call.args, vec![b"1".to_vec(), b"2".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_script_call_namespace() {
+        let config = Redis {
+            namespace: Some("app".to_owned()),
+            ..testing::config()
+        };
+        let client = Client::new(&config).unwrap();
+        let call = client.builtin_script("get", SCRIPT).key("a").arg("b");
+        assert_eq!(call.keys, vec![b"app:a".to_vec()]);
+        assert_eq!(call.args, vec![b"b".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_script_not_connected() {
+        let client = client();
+        client.register_script("get", SCRIPT);
+        let res = client.script("get").unwrap().invoke::<()>().await;
+        assert!(matches!(res, Err(Error::Connection)));
+    }
+}
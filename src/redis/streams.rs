@@ -0,0 +1,498 @@
+use std::{collections::HashMap, fmt::Display, future::Future, time::Duration};
+
+use log::{debug, error, warn};
+use redis::{
+    from_redis_value,
+    streams::{StreamReadOptions, StreamReadReply},
+    Cmd, ErrorKind, FromRedisValue, RedisResult, ToRedisArgs, Value,
+};
+use tokio::time::Instant;
+
+pub use redis::streams::{StreamId, StreamPendingCountReply, StreamPendingReply};
+
+use super::{Client, Error, ManagedConnection, Result};
+
+const ERROR_DELAY: Duration = Duration::from_secs(1);
+
+///Reply of the `XAUTOCLAIM` command.
+#[derive(Debug, Clone, Default)]
+pub struct AutoClaimReply {
+    ///id to use as start for the next call, `0-0` when the whole pending
+    ///list has been scanned
+    pub next: String,
+    ///claimed entries
+    pub claimed: Vec<StreamId>,
+    ///ids of pending entries that no longer exist in the stream, they are
+    ///removed from the pending entries by the command
+    pub deleted: Vec<String>,
+}
+
+///parse stream entries, skipping the entries deleted from the stream.
+fn parse_entries(value: &Value) -> RedisResult<Vec<StreamId>> {
+    let entries: Vec<Value> = from_redis_value(value)?;
+    let mut ids = Vec::with_capacity(entries.len());
+    for entry in entries.iter().filter(|e| **e != Value::Nil) {
+        let (id, map): (String, Option<HashMap<String, Value>>) = from_redis_value(entry)?;
+        ids.push(StreamId {
+            id,
+            map: map.unwrap_or_default(),
+        });
+    }
+    Ok(ids)
+}
+
+impl FromRedisValue for AutoClaimReply {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let values: Vec<Value> = from_redis_value(v)?;
+        let mut values = values.iter();
+        let (Some(next), Some(claimed)) = (values.next(), values.next()) else {
+            return Err((ErrorKind::TypeError, "invalid XAUTOCLAIM response").into());
+        };
+        // redis < 7 do not return the deleted ids
+        let deleted = match values.next() {
+            Some(deleted) => from_redis_value(deleted)?,
+            None => Vec::new(),
+        };
+        Ok(AutoClaimReply {
+            next: from_redis_value(next)?,
+            claimed: parse_entries(claimed)?,
+            deleted,
+        })
+    }
+}
+
+fn xreadgroup_cmd(
+    key: &str,
+    group: &str,
+    consumer: &str,
+    count: usize,
+    block: Option<Duration>,
+) -> Cmd {
+    let mut options = StreamReadOptions::default()
+        .group(group, consumer)
+        .count(count);
+    if let Some(block) = block {
+        options = options.block(block.as_millis().try_into().unwrap_or(usize::MAX));
+    }
+    Cmd::xread_options(&[key], &[">"], &options)
+}
+
+fn xautoclaim_cmd(
+    key: &str,
+    group: &str,
+    consumer: &str,
+    min_idle: Duration,
+    start: &str,
+    count: usize,
+) -> Cmd {
+    let mut cmd = redis::cmd("XAUTOCLAIM");
+    cmd.arg(key)
+        .arg(group)
+        .arg(consumer)
+        .arg(min_idle.as_millis().try_into().unwrap_or(u64::MAX))
+        .arg(start)
+        .arg("COUNT")
+        .arg(count);
+    cmd
+}
+
+fn into_entries(reply: Option<StreamReadReply>) -> Vec<StreamId> {
+    reply
+        .map(|r| r.keys.into_iter().flat_map(|k| k.ids).collect())
+        .unwrap_or_default()
+}
+
+impl Client {
+    ///xadd redis command with an auto generated id, return the entry id.
+    pub async fn xadd<F, V>(&self, key: &str, items: &[(F, V)]) -> Result<String>
+    where
+        F: ToRedisArgs,
+        V: ToRedisArgs,
+    {
+        self.query(&Cmd::xadd(self.key(key), "*", items)).await
+    }
+
+    ///create the consumer group and the stream if needed, return false if
+    ///the group already exists.
+    pub async fn xgroup_create(&self, key: &str, group: &str, id: &str) -> Result<bool> {
+        let cmd = Cmd::xgroup_create_mkstream(self.key(key), group, id);
+        match self.query::<()>(&cmd).await {
+            Ok(()) => Ok(true),
+            Err(Error::Redis(e)) if e.code() == Some("BUSYGROUP") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    ///xreadgroup redis command, read up to `count` entries never delivered
+    ///to the group.
+    ///
+    ///Blocking reads are done on a dedicated connection so they do not
+    ///stall the managed one.
+    pub async fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        block: Option<Duration>,
+    ) -> Result<Vec<StreamId>> {
+        let cmd = xreadgroup_cmd(&self.key(key), group, consumer, count, block);
+        let res: Option<StreamReadReply> = match block {
+            Some(block) => {
+                let mut connection = self.get_dedicated_connection().await?;
+                self.with_block_timeout(block, cmd.query_async(&mut connection))
+                    .await?
+            }
+            None => self.query(&cmd).await?,
+        };
+        Ok(into_entries(res))
+    }
+
+    ///xack redis command, return the number of acknowledged entries.
+    pub async fn xack<I>(&self, key: &str, group: &str, ids: &[I]) -> Result<usize>
+    where
+        I: ToRedisArgs,
+    {
+        self.query_idempotent(&Cmd::xack(self.key(key), group, ids))
+            .await
+    }
+
+    ///xpending redis command, return the summary of the group pending
+    ///entries.
+    pub async fn xpending(&self, key: &str, group: &str) -> Result<StreamPendingReply> {
+        self.query_idempotent(&Cmd::xpending(self.key(key), group))
+            .await
+    }
+
+    ///xpending redis command with range, return the details of up to
+    ///`count` pending entries.
+    pub async fn xpending_count(
+        &self,
+        key: &str,
+        group: &str,
+        count: usize,
+    ) -> Result<StreamPendingCountReply> {
+        let cmd = Cmd::xpending_count(self.key(key), group, "-", "+", count);
+        self.query_idempotent(&cmd).await
+    }
+
+    ///xautoclaim redis command, transfer to `consumer` up to `count` pending
+    ///entries idle for more than `min_idle`.
+    pub async fn xautoclaim(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        start: &str,
+        count: usize,
+    ) -> Result<AutoClaimReply> {
+        let cmd = xautoclaim_cmd(&self.key(key), group, consumer, min_idle, start, count);
+        self.query(&cmd).await
+    }
+}
+
+///Member of a consumer group processing the entries of a stream.
+///
+///Each entry is given to the handler, it is acknowledged if the handler
+///succeed and left pending otherwise. Pending entries idle for more than
+///`claim_idle` are reclaimed and processed again.
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    client: Client,
+    key: String,
+    group: String,
+    name: String,
+    batch_size: usize,
+    block: Duration,
+    claim_idle: Duration,
+}
+
+impl Consumer {
+    pub fn new<K, G, N>(client: &Client, key: K, group: G, name: N) -> Self
+    where
+        K: Into<String>,
+        G: Into<String>,
+        N: Into<String>,
+    {
+        Consumer {
+            client: client.clone(),
+            key: key.into(),
+            group: group.into(),
+            name: name.into(),
+            batch_size: 10,
+            block: Duration::from_secs(5),
+            claim_idle: Duration::from_secs(60),
+        }
+    }
+
+    ///maximum number of entries read at once
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    ///maximum time to wait for new entries
+    pub fn block(mut self, block: Duration) -> Self {
+        self.block = block;
+        self
+    }
+
+    ///idle time after which a pending entry is reclaimed
+    pub fn claim_idle(mut self, claim_idle: Duration) -> Self {
+        self.claim_idle = claim_idle;
+        self
+    }
+
+    async fn handle<F, Fut, E>(&self, entries: Vec<StreamId>, handler: &F) -> Result<usize>
+    where
+        F: Fn(StreamId) -> Fut,
+        Fut: Future<Output = std::result::Result<(), E>>,
+        E: Display,
+    {
+        let mut acked = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let id = entry.id.clone();
+            match handler(entry).await {
+                Ok(()) => acked.push(id),
+                Err(e) => warn!("failed to process entry {id} of {}: {e}", self.key),
+            }
+        }
+        if acked.is_empty() {
+            return Ok(0);
+        }
+        self.client.xack(&self.key, &self.group, &acked).await
+    }
+
+    ///reclaim the stuck entries and process them, return the number of
+    ///acknowledged entries.
+    pub async fn reclaim<F, Fut, E>(&self, handler: &F) -> Result<usize>
+    where
+        F: Fn(StreamId) -> Fut,
+        Fut: Future<Output = std::result::Result<(), E>>,
+        E: Display,
+    {
+        let mut start = "0-0".to_owned();
+        let mut acked = 0;
+        loop {
+            let reply = self
+                .client
+                .xautoclaim(
+                    &self.key,
+                    &self.group,
+                    &self.name,
+                    self.claim_idle,
+                    &start,
+                    self.batch_size,
+                )
+                .await?;
+            // XAUTOCLAIM already removed them from the pending entries
+            if !reply.deleted.is_empty() {
+                debug!("pending entries {:?} were deleted", reply.deleted);
+            }
+            acked += self.handle(reply.claimed, handler).await?;
+            if reply.next == "0-0" {
+                return Ok(acked);
+            }
+            start = reply.next;
+        }
+    }
+
+    async fn read<F, Fut, E>(
+        &self,
+        connection: &mut ManagedConnection,
+        handler: &F,
+    ) -> Result<usize>
+    where
+        F: Fn(StreamId) -> Fut,
+        Fut: Future<Output = std::result::Result<(), E>>,
+        E: Display,
+    {
+        let cmd = xreadgroup_cmd(
+            &self.client.key(&self.key),
+            &self.group,
+            &self.name,
+            self.batch_size,
+            Some(self.block),
+        );
+        let reply: Option<StreamReadReply> = self
+            .client
+            .with_block_timeout(self.block, cmd.query_async(connection))
+            .await?;
+        self.handle(into_entries(reply), handler).await
+    }
+
+    ///create the group if needed then process the stream entries forever.
+    ///
+    ///Blocking reads are done on a dedicated connection so they do not stall
+    ///the shared one.
+    pub async fn run<F, Fut, E>(&self, handler: F) -> Result<()>
+    where
+        F: Fn(StreamId) -> Fut,
+        Fut: Future<Output = std::result::Result<(), E>>,
+        E: Display,
+    {
+        self.client
+            .xgroup_create(&self.key, &self.group, "$")
+            .await?;
+        let mut connection = self.client.target.connect().await?;
+        let mut last_claim: Option<Instant> = None;
+        loop {
+            if !matches!(last_claim, Some(t) if t.elapsed() < self.claim_idle) {
+                last_claim = Some(Instant::now());
+                if let Err(e) = self.reclaim(&handler).await {
+                    error!("failed to reclaim entries of {}: {e}", self.key);
+                }
+            }
+            if let Err(e) = self.read(&mut connection, &handler).await {
+                error!("failed to read entries of {}: {e}", self.key);
+                tokio::time::sleep(ERROR_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_streams {
+    use super::*;
+    use crate::redis::testing::{self, data};
+
+    fn entry(id: &str, field: &str, value: &str) -> Value {
+        Value::Bulk(vec![data(id), Value::Bulk(vec![data(field), data(value)])])
+    }
+
+    #[test]
+    fn test_autoclaim_reply() {
+        let raw = Value::Bulk(vec![
+            data("1-2"),
+            Value::Bulk(vec![entry("1-0", "job", "a"), entry("1-1", "job", "b")]),
+            Value::Bulk(vec![data("0-9")]),
+        ]);
+        let res: AutoClaimReply = from_redis_value(&raw).unwrap();
+        assert_eq!(res.next, "1-2");
+        assert_eq!(res.claimed.len(), 2);
+        assert_eq!(res.claimed[1].id, "1-1");
+        assert_eq!(res.claimed[1].get::<String>("job").as_deref(), Some("b"));
+        assert_eq!(res.deleted, vec!["0-9".to_owned()]);
+    }
+
+    #[test]
+    fn test_autoclaim_reply_redis6() {
+        let raw = Value::Bulk(vec![
+            data("0-0"),
+            Value::Bulk(vec![entry("1-0", "job", "a"), Value::Nil]),
+        ]);
+        let res: AutoClaimReply = from_redis_value(&raw).unwrap();
+        assert_eq!(res.next, "0-0");
+        assert_eq!(res.claimed.len(), 1);
+        assert!(res.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_autoclaim_reply_invalid() {
+        let raw = Value::Bulk(vec![data("0-0")]);
+        assert!(AutoClaimReply::from_redis_value(&raw).is_err());
+    }
+
+    #[test]
+    fn test_xautoclaim_cmd() {
+        let cmd = xautoclaim_cmd("jobs", "workers", "w1", Duration::from_secs(2), "0-0", 5);
+        let args: Vec<Vec<u8>> = cmd
+            .args_iter()
+            .map(|a| match a {
+                redis::Arg::Simple(a) => a.to_vec(),
+                redis::Arg::Cursor => b"cursor".to_vec(),
+            })
+            .collect();
+        let expected: Vec<&[u8]> = vec![
+            b"XAUTOCLAIM",
+            b"jobs",
+            b"workers",
+            b"w1",
+            b"2000",
+            b"0-0",
+            b"COUNT",
+            b"5",
+        ];
+        assert_eq!(args, expected);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_ack() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        client.xgroup_create("ack", "workers", "0").await.unwrap();
+        let id = client.xadd("ack", &[("job", "a")]).await.unwrap();
+        let consumer =
+            Consumer::new(&client, "ack", "workers", "w1").block(Duration::from_millis(100));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            consumer
+                .run(move |entry: StreamId| {
+                    let tx = tx.clone();
+                    async move { tx.send(entry.id).map_err(|e| e.to_string()) }
+                })
+                .await
+        });
+        assert_eq!(rx.recv().await.unwrap(), id);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        task.abort();
+        let pending = client.xpending("ack", "workers").await.unwrap();
+        assert_eq!(pending.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_handler_error() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        client.xgroup_create("error", "workers", "0").await.unwrap();
+        let id = client.xadd("error", &[("job", "a")]).await.unwrap();
+        let consumer = Consumer::new(&client, "error", "workers", "w1");
+        let entries = client
+            .xreadgroup("error", "workers", "w1", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        let handler = |_: StreamId| async { Err::<(), _>("failed") };
+        assert_eq!(consumer.handle(entries, &handler).await.unwrap(), 0);
+        let pending = client.xpending_count("error", "workers", 10).await.unwrap();
+        assert_eq!(pending.ids.len(), 1);
+        assert_eq!(pending.ids[0].id, id);
+        assert_eq!(pending.ids[0].consumer, "w1");
+    }
+
+    #[tokio::test]
+    async fn test_consumer_reclaim() {
+        let Some(client) = testing::server() else {
+            return;
+        };
+        client
+            .xgroup_create("reclaim", "workers", "0")
+            .await
+            .unwrap();
+        let id = client.xadd("reclaim", &[("job", "a")]).await.unwrap();
+        let entries = client
+            .xreadgroup("reclaim", "workers", "w1", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        let consumer = Consumer::new(&client, "reclaim", "workers", "w2")
+            .claim_idle(Duration::from_millis(100));
+        let handler = |entry: StreamId| {
+            let id = id.clone();
+            async move {
+                match entry.id == id {
+                    true => Ok(()),
+                    false => Err(format!("unexpected entry {}", entry.id)),
+                }
+            }
+        };
+        assert_eq!(consumer.reclaim(&handler).await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(consumer.reclaim(&handler).await.unwrap(), 1);
+        let pending = client.xpending("reclaim", "workers").await.unwrap();
+        assert_eq!(pending.count(), 0);
+    }
+}
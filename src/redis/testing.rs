@@ -0,0 +1,40 @@
+use std::env;
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use redis::Value;
+
+use super::{Client, Redis};
+
+///config of a server that is never reached, the clients connect lazily
+pub(super) fn config() -> Redis {
+    Redis {
+        addr: "test.test:8080".to_owned(),
+        ..Default::default()
+    }
+}
+
+pub(super) fn client() -> Client {
+    Client::new(&config()).unwrap()
+}
+
+///client of the server at `REDIS_TEST_ADDR` in a namespace of its own,
+///`None` if the variable is unset so the tests needing a server are skipped
+pub(super) fn server() -> Option<Client> {
+    let addr = env::var("REDIS_TEST_ADDR").ok()?;
+    let namespace: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    let config = Redis {
+        addr,
+        namespace: Some(format!("test-{namespace}")),
+        lazy_connect: true,
+        ..Default::default()
+    };
+    Some(Client::new(&config).unwrap())
+}
+
+pub(super) fn data(s: &str) -> Value {
+    Value::Data(s.as_bytes().to_vec())
+}
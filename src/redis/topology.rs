@@ -0,0 +1,499 @@
+use std::sync::{Arc, RwLock};
+
+use log::{info, warn};
+use redis::{
+    aio::{Connection, ConnectionLike, ConnectionManager},
+    cluster::ClusterClient,
+    cluster_async::ClusterConnection,
+    sentinel::{Sentinel, SentinelNodeConnectionInfo},
+    Cmd, ErrorKind, IntoConnectionInfo, Msg, Pipeline, RedisConnectionInfo, RedisError,
+    RedisFuture, RedisResult, TlsCertificates, Value,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc::UnboundedReceiver, Mutex};
+
+use super::{construc_auth_uri, construc_node_uri, construc_uri, Error, Redis, Result};
+
+///Deployment of the redis servers.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Topology {
+    ///single server reached at `addr`
+    #[default]
+    Standalone,
+    ///master discovered through the sentinels
+    Sentinel {
+        master_name: String,
+        sentinels: Vec<String>,
+        ///user authenticating on the sentinels, they are not authenticated
+        ///if the password is unset
+        sentinel_user: Option<String>,
+        sentinel_password: Option<String>,
+    },
+    ///redis cluster reached through the seed nodes
+    Cluster { nodes: Vec<String> },
+}
+
+///Resolve the master of a sentinel deployment.
+#[derive(Clone)]
+pub(crate) struct SentinelTarget {
+    sentinel: Arc<Mutex<Sentinel>>,
+    master_name: String,
+    node: SentinelNodeConnectionInfo,
+    certificates: Option<TlsCertificates>,
+}
+
+impl SentinelTarget {
+    async fn master(&self) -> RedisResult<redis::Client> {
+        let mut sentinel = self.sentinel.lock().await;
+        let master = sentinel
+            .async_master_for(&self.master_name, Some(&self.node))
+            .await?;
+        open_client(master.get_connection_info().clone(), &self.certificates)
+    }
+}
+
+///open a client with the custom tls certificates if any
+fn open_client<T: IntoConnectionInfo>(
+    info: T,
+    certificates: &Option<TlsCertificates>,
+) -> RedisResult<redis::Client> {
+    match certificates {
+        Some(certificates) => redis::Client::build_with_tls(info, certificates.clone()),
+        None => redis::Client::open(info),
+    }
+}
+
+///Store answering the commands in place of a redis server, like the
+///in-memory one of the tests.
+pub(crate) trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn execute(&self, cmd: &Cmd) -> RedisResult<Value>;
+
+    ///run the commands of the pipeline, the replies are returned as by
+    ///`ConnectionLike::req_packed_commands`
+    fn execute_pipeline(
+        &self,
+        pipe: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>>;
+
+    ///the receiver get the messages published on the channels and the
+    ///patterns after the call
+    fn subscribe(&self, channels: Vec<String>, patterns: Vec<String>) -> UnboundedReceiver<Msg>;
+}
+
+///Connection to a [`Backend`], shared by the clones.
+#[derive(Clone)]
+pub struct BackendConnection {
+    backend: Arc<dyn Backend>,
+}
+
+impl BackendConnection {
+    ///only the in-memory store of the tests is a backend for now
+    #[cfg_attr(not(any(test, feature = "test-support")), allow(dead_code))]
+    pub(crate) fn new<B: Backend + 'static>(backend: B) -> Self {
+        BackendConnection {
+            backend: Arc::new(backend),
+        }
+    }
+
+    pub(crate) fn subscribe(
+        &self,
+        channels: Vec<String>,
+        patterns: Vec<String>,
+    ) -> UnboundedReceiver<Msg> {
+        self.backend.subscribe(channels, patterns)
+    }
+}
+
+impl ConnectionLike for BackendConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let res = self.backend.execute(cmd);
+        Box::pin(async move { res })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let res = self.backend.execute_pipeline(cmd, offset, count);
+        Box::pin(async move { res })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+///Where the client connect to, build from the [`Topology`].
+#[derive(Clone)]
+pub(crate) enum Target {
+    Standalone(redis::Client),
+    Sentinel(SentinelTarget),
+    Cluster(ClusterClient, redis::Client),
+    #[cfg_attr(not(any(test, feature = "test-support")), allow(dead_code))]
+    Backend(BackendConnection),
+}
+
+impl Target {
+    pub(crate) fn new(config: &Redis) -> Result<Self> {
+        let certificates = config.tls_certificates()?;
+        let target = match config.topology {
+            Topology::Standalone => {
+                Target::Standalone(open_client(construc_uri(config)?, &certificates)?)
+            }
+            Topology::Sentinel {
+                ref master_name,
+                ref sentinels,
+                ref sentinel_user,
+                ref sentinel_password,
+            } => {
+                let sentinels = sentinels
+                    .iter()
+                    .map(|addr| {
+                        let url = construc_auth_uri(
+                            config.tls,
+                            sentinel_user.as_deref(),
+                            sentinel_password.as_deref(),
+                            addr,
+                        )?;
+                        Ok(open_client(url, &certificates)?
+                            .get_connection_info()
+                            .clone())
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if config.user.is_some() && config.password.is_none() {
+                    return Err(Error::NoPassword);
+                }
+                let node = SentinelNodeConnectionInfo {
+                    tls_mode: config.tls.then_some(redis::TlsMode::Secure),
+                    redis_connection_info: Some(RedisConnectionInfo {
+                        db: config.db.unwrap_or_default(),
+                        username: config.user.clone(),
+                        password: config.password.clone(),
+                    }),
+                };
+                Target::Sentinel(SentinelTarget {
+                    sentinel: Arc::new(Mutex::new(Sentinel::build(sentinels)?)),
+                    master_name: master_name.to_owned(),
+                    node,
+                    certificates,
+                })
+            }
+            Topology::Cluster { ref nodes } => {
+                let Some(first) = nodes.first() else {
+                    return Err(Error::NoNode);
+                };
+                let urls = nodes
+                    .iter()
+                    .map(|node| construc_node_uri(config, node))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut builder = ClusterClient::builder(urls);
+                if let Some(ref certificates) = certificates {
+                    builder = builder.certs(certificates.clone());
+                }
+                let node = open_client(construc_node_uri(config, first)?, &certificates)?;
+                Target::Cluster(builder.build()?, node)
+            }
+        };
+        Ok(target)
+    }
+
+    ///return a client on a single node: the master for the sentinel, the
+    ///first seed node for the cluster.
+    pub(crate) async fn node_client(&self) -> Result<redis::Client> {
+        match self {
+            Target::Standalone(client) => Ok(client.clone()),
+            Target::Sentinel(sentinel) => Ok(sentinel.master().await?),
+            Target::Cluster(_, node) => Ok(node.clone()),
+            Target::Backend(_) => Err(Error::Unsupported("node connections", self.name())),
+        }
+    }
+
+    ///open an unmanaged connection on a single node
+    pub(crate) async fn simple_connection(&self) -> Result<Connection> {
+        let client = self.node_client().await?;
+        Ok(client.get_tokio_connection().await?)
+    }
+
+    ///open a connection reconnecting automatically
+    pub(crate) async fn connect(&self) -> Result<ManagedConnection> {
+        let connection = match self {
+            Target::Standalone(client) => {
+                ManagedConnection::Single(client.get_connection_manager().await?)
+            }
+            Target::Sentinel(sentinel) => {
+                ManagedConnection::Sentinel(SentinelConnection::new(sentinel.clone()).await?)
+            }
+            Target::Cluster(client, _) => {
+                ManagedConnection::Cluster(client.get_async_connection().await?)
+            }
+            Target::Backend(backend) => ManagedConnection::Backend(backend.clone()),
+        };
+        Ok(connection)
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Target::Standalone(_) => "standalone",
+            Target::Sentinel(_) => "sentinel",
+            Target::Cluster(_, _) => "cluster",
+            Target::Backend(backend) => backend.backend.name(),
+        }
+    }
+
+    ///return false if the lua scripts cannot be run
+    pub(crate) fn supports_scripts(&self) -> bool {
+        !matches!(self, Target::Backend(_))
+    }
+}
+
+///Connection to the sentinel master, the master is resolved again when the
+///connection fail or the server became a replica after a failover.
+///
+///The command is sent again on the new master only if it was not processed
+///by the old one, a command interrupted by a connection failure is returned
+///as an error and retried by the client for the idempotent commands.
+#[derive(Clone)]
+pub struct SentinelConnection {
+    target: SentinelTarget,
+    manager: Arc<RwLock<ConnectionManager>>,
+}
+
+fn is_failover(err: &RedisError) -> bool {
+    err.kind() == ErrorKind::ReadOnly
+        || err.is_io_error()
+        || err.is_connection_dropped()
+        || err.is_connection_refusal()
+}
+
+///return true if the failed command was not processed by the server and can
+///be sent again
+fn is_rejected(err: &RedisError) -> bool {
+    err.kind() == ErrorKind::ReadOnly || err.is_connection_refusal()
+}
+
+impl SentinelConnection {
+    async fn new(target: SentinelTarget) -> RedisResult<Self> {
+        let manager = target.master().await?.get_connection_manager().await?;
+        Ok(SentinelConnection {
+            target,
+            manager: Arc::new(RwLock::new(manager)),
+        })
+    }
+
+    fn manager(&self) -> ConnectionManager {
+        self.manager
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    async fn resolve(&self, err: &RedisError) -> RedisResult<ConnectionManager> {
+        warn!(
+            "lost the sentinel master {}: {err}, resolving it",
+            self.target.master_name
+        );
+        let manager = self.target.master().await?.get_connection_manager().await?;
+        *self.manager.write().unwrap_or_else(|e| e.into_inner()) = manager.clone();
+        info!("sentinel master {} resolved", self.target.master_name);
+        Ok(manager)
+    }
+}
+
+impl ConnectionLike for SentinelConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            match self.manager().req_packed_command(cmd).await {
+                Err(e) if is_rejected(&e) => self.resolve(&e).await?.req_packed_command(cmd).await,
+                Err(e) if is_failover(&e) => {
+                    self.resolve(&e).await?;
+                    Err(e)
+                }
+                res => res,
+            }
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            match self.manager().req_packed_commands(cmd, offset, count).await {
+                Err(e) if is_rejected(&e) => {
+                    self.resolve(&e)
+                        .await?
+                        .req_packed_commands(cmd, offset, count)
+                        .await
+                }
+                Err(e) if is_failover(&e) => {
+                    self.resolve(&e).await?;
+                    Err(e)
+                }
+                res => res,
+            }
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.target
+            .node
+            .redis_connection_info
+            .as_ref()
+            .map_or(0, |info| info.db)
+    }
+}
+
+///Connection shared by the client, commands are dispatched to the
+///connection matching the [`Topology`].
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum ManagedConnection {
+    Single(ConnectionManager),
+    Sentinel(SentinelConnection),
+    Cluster(ClusterConnection),
+    Backend(BackendConnection),
+}
+
+impl ConnectionLike for ManagedConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            ManagedConnection::Single(c) => c.req_packed_command(cmd),
+            ManagedConnection::Sentinel(c) => c.req_packed_command(cmd),
+            ManagedConnection::Cluster(c) => c.req_packed_command(cmd),
+            ManagedConnection::Backend(c) => c.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            ManagedConnection::Single(c) => c.req_packed_commands(cmd, offset, count),
+            ManagedConnection::Sentinel(c) => c.req_packed_commands(cmd, offset, count),
+            ManagedConnection::Cluster(c) => c.req_packed_commands(cmd, offset, count),
+            ManagedConnection::Backend(c) => c.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            ManagedConnection::Single(c) => c.get_db(),
+            ManagedConnection::Sentinel(c) => c.get_db(),
+            ManagedConnection::Cluster(c) => c.get_db(),
+            ManagedConnection::Backend(c) => c.get_db(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_topology {
+    use super::*;
+
+    #[test]
+    fn test_topology_default() {
+        assert_eq!(Topology::default(), Topology::Standalone);
+    }
+
+    #[test]
+    fn test_topology_deserialize() {
+        use figment::{
+            providers::{Format, Yaml},
+            Figment,
+        };
+
+        let yaml = r#"
+            topology:
+              type: sentinel
+              master_name: mymaster
+              sentinels: ["sentinel1:26379", "sentinel2:26379"]
+        "#;
+        let config: Redis = Figment::new().merge(Yaml::string(yaml)).extract().unwrap();
+        let expected = Topology::Sentinel {
+            master_name: "mymaster".to_owned(),
+            sentinels: vec!["sentinel1:26379".to_owned(), "sentinel2:26379".to_owned()],
+            sentinel_user: None,
+            sentinel_password: None,
+        };
+        assert_eq!(config.topology, expected);
+        assert!(config.addr.is_empty());
+    }
+
+    #[test]
+    fn test_target_cluster_no_node() {
+        let config = Redis {
+            topology: Topology::Cluster { nodes: vec![] },
+            ..Default::default()
+        };
+        assert!(matches!(Target::new(&config), Err(Error::NoNode)));
+    }
+
+    #[test]
+    fn test_target_cluster() {
+        let config = Redis {
+            password: Some("tata64".to_owned()),
+            topology: Topology::Cluster {
+                nodes: vec!["node1:6379".to_owned(), "node2:6379".to_owned()],
+            },
+            ..Default::default()
+        };
+        let target = Target::new(&config).unwrap();
+        assert_eq!(target.name(), "cluster");
+    }
+
+    #[test]
+    fn test_target_sentinel() {
+        let config = Redis {
+            db: Some(2),
+            topology: Topology::Sentinel {
+                master_name: "mymaster".to_owned(),
+                sentinels: vec!["sentinel1:26379".to_owned()],
+                sentinel_user: None,
+                sentinel_password: Some("sentinel".to_owned()),
+            },
+            ..Default::default()
+        };
+        let target = Target::new(&config).unwrap();
+        assert_eq!(target.name(), "sentinel");
+        let Target::Sentinel(sentinel) = target else {
+            unreachable!()
+        };
+        let info = sentinel.node.redis_connection_info.unwrap();
+        assert_eq!(info.db, 2);
+        assert!(sentinel.certificates.is_none());
+    }
+
+    #[test]
+    fn test_target_sentinel_no_password() {
+        let config = Redis {
+            topology: Topology::Sentinel {
+                master_name: "mymaster".to_owned(),
+                sentinels: vec!["sentinel1:26379".to_owned()],
+                sentinel_user: Some("admin".to_owned()),
+                sentinel_password: None,
+            },
+            ..Default::default()
+        };
+        assert!(matches!(Target::new(&config), Err(Error::NoPassword)));
+    }
+
+    #[test]
+    fn test_is_rejected() {
+        let readonly = RedisError::from((ErrorKind::ReadOnly, "replica"));
+        assert!(is_rejected(&readonly));
+        let io = RedisError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(is_failover(&io));
+        assert!(!is_rejected(&io));
+    }
+}